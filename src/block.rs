@@ -0,0 +1,286 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+
+use crate::Filter;
+
+/// A single `<name>=<value>` entry from an [`EnvBlock`].
+///
+/// Keys and values are arbitrary bytes, there's no guarantee that they're valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EnvEntry<'a> {
+    pub key: &'a [u8],
+    pub value: &'a [u8],
+}
+
+impl<'a> EnvEntry<'a> {
+    /// Parse a single entry (without the trailing NUL). Everything up to the first `=` is the
+    /// key, and the rest is the value. If there's no `=` at all, the value is empty.
+    pub fn parse(chunk: &'a [u8]) -> Self {
+        // [T].split_once() is still unstable :(
+        match chunk.iter().position(|b| *b == b'=') {
+            Some(pos) => {
+                let (key, value) = chunk.split_at(pos);
+                Self {
+                    key,
+                    value: &value[1..],
+                }
+            }
+            None => Self {
+                key: chunk,
+                value: &[],
+            },
+        }
+    }
+}
+
+impl fmt::Display for EnvEntry<'_> {
+    /// Lossy display as `key=value`, replacing invalid UTF-8 with U+FFFD
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}={}",
+            String::from_utf8_lossy(self.key),
+            String::from_utf8_lossy(self.value)
+        )
+    }
+}
+
+/// An owned environment block, a buffer of `<name>=<value>\0` entries.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvBlock {
+    buf: Vec<u8>,
+}
+
+impl EnvBlock {
+    /// Wrap an existing buffer
+    pub fn new(buf: Vec<u8>) -> Self {
+        Self { buf }
+    }
+
+    /// Read the environment block of the file at `path`
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        std::fs::read(path).map(Self::new)
+    }
+
+    /// Read the initial environment of a process from `/proc/<pid>/environ`
+    pub fn from_pid(pid: u32) -> io::Result<Self> {
+        Self::from_file(format!("/proc/{pid}/environ"))
+    }
+
+    /// Read the initial environment of a crashed process from its ELF core dump, see
+    /// [`coredump`](crate::coredump).
+    pub fn from_core_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        crate::coredump::read_core_file(path)
+    }
+
+    /// Read an environment block from `reader` until EOF
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        Ok(Self::new(buf))
+    }
+
+    /// The raw bytes of this block
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Iterate over the entries in this block, in their original order. Empty entries (e.g.
+    /// from a doubled or trailing NUL) are skipped.
+    pub fn entries(&self) -> Entries<'_> {
+        Entries {
+            inner: self.buf.split(is_nul as fn(&u8) -> bool),
+        }
+    }
+
+    /// Collect the entries matching `filter` into a map, keeping the first value of duplicate
+    /// keys since that's the one that `getenv` returns.
+    pub fn to_map<F: Filter + ?Sized>(&self, filter: &F) -> BTreeMap<&[u8], &[u8]> {
+        let mut map = BTreeMap::new();
+        for entry in self.entries().filter(|e| filter.matches(e)) {
+            map.entry(entry.key).or_insert(entry.value);
+        }
+        map
+    }
+}
+
+impl From<Vec<u8>> for EnvBlock {
+    fn from(buf: Vec<u8>) -> Self {
+        Self::new(buf)
+    }
+}
+
+fn is_nul(b: &u8) -> bool {
+    *b == b'\0'
+}
+
+/// Iterator over the entries of an [`EnvBlock`], created by [`EnvBlock::entries`].
+#[derive(Debug, Clone)]
+pub struct Entries<'a> {
+    inner: std::slice::Split<'a, u8, fn(&u8) -> bool>,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = EnvEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .by_ref()
+            .find(|chunk| !chunk.is_empty())
+            .map(EnvEntry::parse)
+    }
+}
+
+/// Reads the entries of an environment block one at a time from a stream.
+///
+/// Unlike [`EnvBlock::from_reader`], this doesn't need to see the end of the input before
+/// returning the first entry, and memory use is bounded by the size of the largest entry, so it
+/// works on endless streams like a FIFO.
+#[derive(Debug)]
+pub struct EnvReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    max_len: Option<usize>,
+}
+
+impl<R: BufRead> EnvReader<R> {
+    /// By default, entries longer than this are an error rather than buffered indefinitely. Linux
+    /// limits each environment string to 128 KiB, so anything this large from a pipe is almost
+    /// certainly not an environment.
+    pub const MAX_ENTRY_LEN: usize = 1 << 20;
+
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            max_len: Some(Self::MAX_ENTRY_LEN),
+        }
+    }
+
+    /// Change the limit on the length of an entry, or remove it with `None`. That's fine for input
+    /// which is known to end, like a regular file.
+    pub fn max_entry_len(mut self, max_len: Option<usize>) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Read the next entry, or `None` at EOF. Like [`EnvBlock::entries`], empty entries are
+    /// skipped. The final entry doesn't need a trailing NUL.
+    pub fn next_entry(&mut self) -> io::Result<Option<EnvEntry<'_>>> {
+        loop {
+            self.buf.clear();
+            // read one byte past the limit to tell whether it was exceeded
+            let limit = self.max_len.map_or(u64::MAX, |max| max as u64 + 1);
+            let len = (&mut self.reader)
+                .take(limit)
+                .read_until(b'\0', &mut self.buf)?;
+            if len == 0 {
+                return Ok(None);
+            }
+            if self.buf.last() == Some(&b'\0') {
+                self.buf.pop();
+            } else if let Some(max) = self.max_len
+                && len > max
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("entry longer than {max} bytes"),
+                ));
+            }
+            if !self.buf.is_empty() {
+                return Ok(Some(EnvEntry::parse(&self.buf)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the input a few bytes at a time, so that entries are split across reads
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn read_all<R: BufRead>(mut reader: EnvReader<R>) -> io::Result<Vec<String>> {
+        let mut entries = Vec::new();
+        while let Some(entry) = reader.next_entry()? {
+            entries.push(entry.to_string());
+        }
+        Ok(entries)
+    }
+
+    fn chunked(data: &[u8], chunk: usize) -> EnvReader<io::BufReader<Chunked<'_>>> {
+        // a tiny buffer, so that entries are split across reads from the BufReader too
+        EnvReader::new(io::BufReader::with_capacity(2, Chunked { data, chunk }))
+    }
+
+    #[test]
+    fn reader_split_entries() {
+        let data = b"A=1\0BB=two\0CCC=three\0";
+        for chunk in 1..data.len() {
+            assert_eq!(
+                read_all(chunked(data, chunk)).unwrap(),
+                ["A=1", "BB=two", "CCC=three"]
+            );
+        }
+    }
+
+    #[test]
+    fn reader_no_trailing_nul() {
+        assert_eq!(
+            read_all(EnvReader::new(&b"A=1\0B=2"[..])).unwrap(),
+            ["A=1", "B=2"]
+        );
+    }
+
+    #[test]
+    fn reader_empty_entries() {
+        assert_eq!(
+            read_all(EnvReader::new(&b"\0\0A=1\0\0\0B\0\0"[..])).unwrap(),
+            ["A=1", "B="]
+        );
+        assert!(read_all(EnvReader::new(&b""[..])).unwrap().is_empty());
+        assert!(read_all(EnvReader::new(&b"\0\0"[..])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reader_length_limit() {
+        let data = b"A=1\0B=123456\0C=3\0";
+        let read = |max| read_all(EnvReader::new(&data[..]).max_entry_len(max));
+
+        // exactly at the limit is fine, with or without the NUL
+        assert_eq!(read(Some(8)).unwrap(), ["A=1", "B=123456", "C=3"]);
+        assert_eq!(
+            read_all(EnvReader::new(&b"B=123456"[..]).max_entry_len(Some(8))).unwrap(),
+            ["B=123456"]
+        );
+        assert_eq!(
+            read(Some(7)).unwrap_err().to_string(),
+            "entry longer than 7 bytes"
+        );
+        assert_eq!(read(None).unwrap(), ["A=1", "B=123456", "C=3"]);
+
+        let big = vec![b'x'; EnvReader::<&[u8]>::MAX_ENTRY_LEN + 1];
+        assert!(read_all(EnvReader::new(&big[..])).is_err());
+        assert_eq!(
+            read_all(EnvReader::new(&big[..]).max_entry_len(None))
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
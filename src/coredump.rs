@@ -0,0 +1,113 @@
+//! Extract the environment block from an ELF core dump.
+//!
+//! The kernel saves the process' auxiliary vector in an `NT_AUXV` note. On the stack, the auxv
+//! is placed right after the NULL-terminated `envp` array, so we find the auxv in the dumped
+//! stack segment and walk backwards to get the `envp` pointers, then read each string.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek};
+use std::path::Path;
+
+use crate::EnvBlock;
+use crate::elf::{ET_CORE, Elf, PT_LOAD, Segment, invalid};
+
+const NT_AUXV: u32 = 6;
+const AT_NULL: u64 = 0;
+const AT_EXECFN: u64 = 31;
+
+/// Read the initial environment of the crashed process from the core dump at `path`
+pub fn read_core_file(path: impl AsRef<Path>) -> io::Result<EnvBlock> {
+    read_core(BufReader::new(File::open(path)?))
+}
+
+/// Read the initial environment of the crashed process from a core dump
+pub fn read_core<R: Read + Seek>(reader: R) -> io::Result<EnvBlock> {
+    let mut core = Elf::new(reader)?;
+    if core.e_type != ET_CORE {
+        return Err(invalid("not a core dump"));
+    }
+
+    let auxv = core
+        .find_note(NT_AUXV)?
+        .ok_or_else(|| invalid("core dump has no NT_AUXV note"))?;
+    let w = core.word_size();
+    let execfn = auxv
+        .chunks_exact(2 * w)
+        .map(|pair| (core.word(pair), core.word(&pair[w..])))
+        .take_while(|(key, _)| *key != AT_NULL)
+        .find(|(key, _)| *key == AT_EXECFN)
+        .map(|(_, val)| val);
+
+    // AT_EXECFN points into the stack, so search that segment first. Fall back to everything in
+    // case it's missing.
+    let mut segments: Vec<Segment> = core
+        .segments
+        .iter()
+        .filter(|seg| seg.p_type == PT_LOAD && seg.filesz > 0)
+        .copied()
+        .collect();
+    if let Some(execfn) = execfn {
+        segments.sort_by_key(|seg| !seg.contains(execfn));
+    }
+    for seg in segments {
+        let data = core.segment_data(&seg)?;
+        if let Some(pos) = find(&data, &auxv) {
+            return read_environ(&core, &seg, &data, pos);
+        }
+    }
+    Err(invalid("couldn't find the stack in the core dump"))
+}
+
+/// Given that the auxv starts at offset `pos` in the data of segment `seg`, walk backwards
+/// through the envp array and read all the environment strings
+fn read_environ<R: Read + Seek>(
+    core: &Elf<R>,
+    seg: &Segment,
+    data: &[u8],
+    pos: usize,
+) -> io::Result<EnvBlock> {
+    let w = core.word_size();
+
+    // the word right before the auxv is envp's NULL terminator, and envp is preceded by argv's
+    // NULL terminator
+    let mut end = pos
+        .checked_sub(w)
+        .ok_or_else(|| invalid("no envp before auxv"))?;
+    if core.word(&data[end..]) != 0 {
+        return Err(invalid("envp isn't NULL-terminated"));
+    }
+    let mut ptrs = Vec::new();
+    while let Some(start) = end.checked_sub(w) {
+        let ptr = core.word(&data[start..]);
+        if ptr == 0 {
+            break;
+        }
+        ptrs.push(ptr);
+        end = start;
+    }
+    ptrs.reverse();
+
+    let mut buf = Vec::new();
+    for ptr in ptrs {
+        if !seg.contains(ptr) {
+            return Err(invalid(format!(
+                "environment pointer {ptr:#x} is outside the stack"
+            )));
+        }
+        let start = (ptr - seg.vaddr) as usize;
+        let len = data[start..]
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| invalid("unterminated environment string"))?;
+        buf.extend_from_slice(&data[start..start + len + 1]);
+    }
+    Ok(EnvBlock::new(buf))
+}
+
+/// Find the first occurrence of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
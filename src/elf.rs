@@ -0,0 +1,403 @@
+//! Just enough ELF parsing to read core dumps and look up dynamic symbols
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+pub const ET_CORE: u16 = 4;
+pub const PT_LOAD: u32 = 1;
+pub const PT_NOTE: u32 = 4;
+const SHT_DYNSYM: u32 = 11;
+
+pub fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// A program header
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub p_type: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+}
+
+impl Segment {
+    /// Whether `addr` is in the part of this segment that's backed by the file
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.vaddr && addr - self.vaddr < self.filesz
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Section {
+    sh_type: u32,
+    offset: u64,
+    size: u64,
+    link: u32,
+}
+
+pub struct Elf<R> {
+    reader: R,
+    pub is_64: bool,
+    big_endian: bool,
+    pub e_type: u16,
+    pub segments: Vec<Segment>,
+    /// size of the whole file, to reject offsets and sizes from corrupted headers before
+    /// allocating buffers for them
+    len: u64,
+    shoff: u64,
+    shentsize: u16,
+    shnum: u16,
+}
+
+impl<R: Read + Seek> Elf<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut ident = [0u8; 16];
+        reader.read_exact(&mut ident)?;
+        if &ident[..4] != b"\x7fELF" {
+            return Err(invalid("not an ELF file"));
+        }
+        let is_64 = match ident[4] {
+            1 => false,
+            2 => true,
+            _ => return Err(invalid("unknown ELF class")),
+        };
+        let big_endian = match ident[5] {
+            1 => false,
+            2 => true,
+            _ => return Err(invalid("unknown ELF byte order")),
+        };
+
+        let len = reader.seek(SeekFrom::End(0))?;
+        let mut elf = Self {
+            reader,
+            is_64,
+            big_endian,
+            e_type: 0,
+            segments: Vec::new(),
+            len,
+            shoff: 0,
+            shentsize: 0,
+            shnum: 0,
+        };
+
+        let header = elf.read_at(0, if is_64 { 64 } else { 52 })?;
+        elf.e_type = elf.u16(&header[16..]);
+        let (phoff, phentsize, phnum) = if is_64 {
+            elf.shoff = elf.u64(&header[40..]);
+            elf.shentsize = elf.u16(&header[58..]);
+            elf.shnum = elf.u16(&header[60..]);
+            (
+                elf.u64(&header[32..]),
+                elf.u16(&header[54..]),
+                elf.u16(&header[56..]),
+            )
+        } else {
+            elf.shoff = u64::from(elf.u32(&header[32..]));
+            elf.shentsize = elf.u16(&header[46..]);
+            elf.shnum = elf.u16(&header[48..]);
+            (
+                u64::from(elf.u32(&header[28..])),
+                elf.u16(&header[42..]),
+                elf.u16(&header[44..]),
+            )
+        };
+
+        if phnum == 0 {
+            return Ok(elf);
+        }
+        if phentsize < if is_64 { 56 } else { 32 } {
+            return Err(invalid("program header entries are too small"));
+        }
+        let phdrs = elf.read_at(phoff, usize::from(phentsize) * usize::from(phnum))?;
+        for phdr in phdrs.chunks_exact(usize::from(phentsize)) {
+            let seg = if is_64 {
+                Segment {
+                    p_type: elf.u32(phdr),
+                    offset: elf.u64(&phdr[8..]),
+                    vaddr: elf.u64(&phdr[16..]),
+                    filesz: elf.u64(&phdr[32..]),
+                }
+            } else {
+                Segment {
+                    p_type: elf.u32(phdr),
+                    offset: u64::from(elf.u32(&phdr[4..])),
+                    vaddr: u64::from(elf.u32(&phdr[8..])),
+                    filesz: u64::from(elf.u32(&phdr[16..])),
+                }
+            };
+            elf.segments.push(seg);
+        }
+        Ok(elf)
+    }
+
+    /// Read `len` bytes at `offset`, which must be inside the file
+    pub fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        if offset
+            .checked_add(len as u64)
+            .is_none_or(|end| end > self.len)
+        {
+            return Err(invalid(format!(
+                "{len} bytes at offset {offset:#x} are past the end of the file"
+            )));
+        }
+        let mut buf = vec![0; len];
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.reader.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read the file contents of a segment
+    pub fn segment_data(&mut self, seg: &Segment) -> io::Result<Vec<u8>> {
+        let len = usize::try_from(seg.filesz).map_err(|_| invalid("segment too large"))?;
+        self.read_at(seg.offset, len)
+    }
+
+    pub fn u16(&self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        }
+    }
+
+    pub fn u32(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+
+    pub fn u64(&self, b: &[u8]) -> u64 {
+        let b = [b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]];
+        if self.big_endian {
+            u64::from_be_bytes(b)
+        } else {
+            u64::from_le_bytes(b)
+        }
+    }
+
+    /// Size of a native pointer
+    pub fn word_size(&self) -> usize {
+        if self.is_64 { 8 } else { 4 }
+    }
+
+    /// Read a native pointer-sized word
+    pub fn word(&self, b: &[u8]) -> u64 {
+        if self.is_64 {
+            self.u64(b)
+        } else {
+            u64::from(self.u32(b))
+        }
+    }
+
+    fn sections(&mut self) -> io::Result<Vec<Section>> {
+        if self.shnum == 0 {
+            return Ok(Vec::new());
+        }
+        if self.shentsize < if self.is_64 { 64 } else { 40 } {
+            return Err(invalid("section header entries are too small"));
+        }
+        let shdrs = self.read_at(
+            self.shoff,
+            usize::from(self.shentsize) * usize::from(self.shnum),
+        )?;
+        Ok(shdrs
+            .chunks_exact(usize::from(self.shentsize))
+            .map(|shdr| {
+                if self.is_64 {
+                    Section {
+                        sh_type: self.u32(&shdr[4..]),
+                        offset: self.u64(&shdr[24..]),
+                        size: self.u64(&shdr[32..]),
+                        link: self.u32(&shdr[40..]),
+                    }
+                } else {
+                    Section {
+                        sh_type: self.u32(&shdr[4..]),
+                        offset: u64::from(self.u32(&shdr[16..])),
+                        size: u64::from(self.u32(&shdr[20..])),
+                        link: self.u32(&shdr[24..]),
+                    }
+                }
+            })
+            .collect())
+    }
+
+    fn section_data(&mut self, sec: &Section) -> io::Result<Vec<u8>> {
+        let len = usize::try_from(sec.size).map_err(|_| invalid("section too large"))?;
+        self.read_at(sec.offset, len)
+    }
+
+    /// Look up the value (i.e. unrelocated address) of a symbol in the dynamic symbol table
+    pub fn dynamic_symbol(&mut self, name: &str) -> io::Result<Option<u64>> {
+        let sections = self.sections()?;
+        let Some(dynsym) = sections.iter().find(|s| s.sh_type == SHT_DYNSYM) else {
+            return Ok(None);
+        };
+        let strtab = sections
+            .get(dynsym.link as usize)
+            .ok_or_else(|| invalid("bad dynsym string table index"))?;
+        let symbols = self.section_data(dynsym)?;
+        let strings = self.section_data(strtab)?;
+
+        let (entsize, value_at, shndx_at) = if self.is_64 { (24, 8, 6) } else { (16, 4, 14) };
+        for sym in symbols.chunks_exact(entsize) {
+            let name_off = self.u32(sym) as usize;
+            let Some(sym_name) = strings.get(name_off..) else {
+                continue;
+            };
+            let len = sym_name.iter().position(|b| *b == 0).unwrap_or(0);
+            // skip undefined symbols, they're references to another object's copy
+            if &sym_name[..len] == name.as_bytes() && self.u16(&sym[shndx_at..]) != 0 {
+                return Ok(Some(self.word(&sym[value_at..])));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Find the descriptor of the first note with type `n_type`
+    pub fn find_note(&mut self, n_type: u32) -> io::Result<Option<Vec<u8>>> {
+        let notes: Vec<Segment> = self
+            .segments
+            .iter()
+            .filter(|seg| seg.p_type == PT_NOTE)
+            .copied()
+            .collect();
+        for seg in notes {
+            let data = self.segment_data(&seg)?;
+            let mut pos = 0;
+            // notes in core dumps are always 4-byte aligned, even on 64-bit
+            while pos + 12 <= data.len() {
+                let namesz = self.u32(&data[pos..]) as usize;
+                let descsz = self.u32(&data[pos + 4..]) as usize;
+                let typ = self.u32(&data[pos + 8..]);
+                // sizes come straight from the file, so watch out for overflow
+                let desc_start = namesz
+                    .checked_next_multiple_of(4)
+                    .and_then(|namesz| (pos + 12).checked_add(namesz));
+                let desc_end = desc_start
+                    .and_then(|start| start.checked_add(descsz))
+                    .filter(|end| *end <= data.len());
+                let (Some(desc_start), Some(desc_end)) = (desc_start, desc_end) else {
+                    return Err(invalid("truncated note"));
+                };
+                if typ == n_type {
+                    return Ok(Some(data[desc_start..desc_end].to_vec()));
+                }
+                pos = desc_end.next_multiple_of(4);
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A 64-bit little-endian core file header
+    fn header(phoff: u64, phentsize: u16, phnum: u16, shentsize: u16, shnum: u16) -> Vec<u8> {
+        let mut buf = vec![0; 64];
+        buf[..7].copy_from_slice(b"\x7fELF\x02\x01\x01");
+        buf[16..18].copy_from_slice(&ET_CORE.to_le_bytes());
+        buf[32..40].copy_from_slice(&phoff.to_le_bytes());
+        buf[40..48].copy_from_slice(&64u64.to_le_bytes());
+        buf[54..56].copy_from_slice(&phentsize.to_le_bytes());
+        buf[56..58].copy_from_slice(&phnum.to_le_bytes());
+        buf[58..60].copy_from_slice(&shentsize.to_le_bytes());
+        buf[60..62].copy_from_slice(&shnum.to_le_bytes());
+        buf
+    }
+
+    /// A core file with one PT_NOTE segment holding `notes`
+    fn core_with_notes(notes: &[u8]) -> Vec<u8> {
+        let mut buf = header(64, 56, 1, 0, 0);
+        let mut phdr = vec![0; 56];
+        phdr[..4].copy_from_slice(&PT_NOTE.to_le_bytes());
+        phdr[8..16].copy_from_slice(&120u64.to_le_bytes());
+        phdr[32..40].copy_from_slice(&(notes.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&phdr);
+        buf.extend_from_slice(notes);
+        buf
+    }
+
+    fn note(namesz: u32, descsz: u32, n_type: u32, rest: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&namesz.to_le_bytes());
+        buf.extend_from_slice(&descsz.to_le_bytes());
+        buf.extend_from_slice(&n_type.to_le_bytes());
+        buf.extend_from_slice(rest);
+        buf
+    }
+
+    fn parse(buf: Vec<u8>) -> io::Result<Elf<Cursor<Vec<u8>>>> {
+        Elf::new(Cursor::new(buf))
+    }
+
+    #[test]
+    fn truncated_header() {
+        let buf = header(64, 56, 1, 0, 0);
+        for len in [0, 4, 16, 40, 63] {
+            assert!(parse(buf[..len].to_vec()).is_err(), "len {len}");
+        }
+        // the program headers themselves are missing
+        assert!(parse(buf).is_err());
+    }
+
+    #[test]
+    fn bad_program_header_size() {
+        for phentsize in [0, 8, 55] {
+            let mut buf = header(64, phentsize, 1, 0, 0);
+            buf.resize(256, 0);
+            assert!(parse(buf).is_err(), "phentsize {phentsize}");
+        }
+    }
+
+    #[test]
+    fn program_headers_past_end() {
+        assert!(parse(header(64, 56, u16::MAX, 0, 0)).is_err());
+        assert!(parse(header(u64::MAX, 56, 1, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn bad_section_header_size() {
+        for shentsize in [0, 8, 63] {
+            let mut buf = header(64, 56, 0, shentsize, 1);
+            buf.resize(256, 0);
+            let mut elf = parse(buf).unwrap();
+            assert!(
+                elf.dynamic_symbol("environ").is_err(),
+                "shentsize {shentsize}"
+            );
+        }
+        let mut elf = parse(header(64, 56, 0, 64, u16::MAX)).unwrap();
+        assert!(elf.dynamic_symbol("environ").is_err());
+    }
+
+    #[test]
+    fn notes() {
+        let mut notes = note(5, 4, 1, b"CORE\0\0\0\0abcd");
+        notes.extend(note(0, 2, 6, b"xy\0\0"));
+        let mut elf = parse(core_with_notes(&notes)).unwrap();
+        assert_eq!(elf.find_note(1).unwrap().as_deref(), Some(&b"abcd"[..]));
+        assert_eq!(elf.find_note(6).unwrap().as_deref(), Some(&b"xy"[..]));
+        assert_eq!(elf.find_note(7).unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_notes() {
+        for notes in [
+            note(u32::MAX, 0, 6, &[]),
+            note(0, u32::MAX, 6, &[]),
+            note(u32::MAX - 2, u32::MAX, 6, &[]),
+            note(4, 8, 6, b"CORE1234"),
+        ] {
+            let mut elf = parse(core_with_notes(&notes)).unwrap();
+            assert!(elf.find_note(6).is_err());
+        }
+    }
+}
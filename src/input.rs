@@ -0,0 +1,385 @@
+//! Parsers for environment formats other than NUL-separated blocks.
+//!
+//! Everything is converted to a normal [`EnvBlock`], so that filtering and printing work the same
+//! regardless of where the environment came from.
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use crate::EnvBlock;
+
+/// The format of an environment file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// `<name>=<value>\0` entries, like `/proc/<pid>/environ` or `env -0`
+    #[default]
+    Nul,
+    /// One `<name>=<value>` entry per line, values can't contain newlines
+    Newline,
+    /// A `.env` file, with comments, quoting, and optional `export` prefixes
+    Dotenv,
+    /// Output of `env` or `printenv`, where lines that don't start with `<name>=` are a
+    /// continuation of the previous value
+    EnvOutput,
+}
+
+impl InputFormat {
+    pub const NAMES: &[&str] = &["nul", "newline", "dotenv", "env-output"];
+
+    /// Convert `buf` to an environment block
+    pub fn parse(self, buf: Vec<u8>) -> io::Result<EnvBlock> {
+        match self {
+            Self::Nul => Ok(EnvBlock::new(buf)),
+            Self::Newline => Ok(parse_newline(&buf)),
+            Self::Dotenv => parse_dotenv(&buf),
+            Self::EnvOutput => Ok(parse_env_output(&buf)),
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nul" => Ok(Self::Nul),
+            "newline" => Ok(Self::Newline),
+            "dotenv" => Ok(Self::Dotenv),
+            "env-output" => Ok(Self::EnvOutput),
+            _ => Err(format!("unknown input format '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Nul => "nul",
+            Self::Newline => "newline",
+            Self::Dotenv => "dotenv",
+            Self::EnvOutput => "env-output",
+        })
+    }
+}
+
+/// Split into lines, dropping the `\r` of CRLF line endings
+fn lines(buf: &[u8]) -> impl Iterator<Item = &[u8]> {
+    buf.split(|b| *b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+}
+
+fn push_entry(out: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    out.extend_from_slice(key);
+    out.push(b'=');
+    out.extend_from_slice(value);
+    out.push(b'\0');
+}
+
+fn parse_newline(buf: &[u8]) -> EnvBlock {
+    let mut out = Vec::with_capacity(buf.len());
+    for line in lines(buf).filter(|line| !line.is_empty()) {
+        out.extend_from_slice(line);
+        out.push(b'\0');
+    }
+    EnvBlock::new(out)
+}
+
+/// Whether `line` starts with `<name>=`, where name is a shell identifier or a bash exported
+/// function (`BASH_FUNC_<name>%%`)
+fn starts_entry(line: &[u8]) -> bool {
+    let Some(eq) = line.iter().position(|b| *b == b'=') else {
+        return false;
+    };
+    let name = &line[..eq];
+    let name = name.strip_suffix(b"%%").unwrap_or(name);
+    match name.split_first() {
+        Some((first, rest)) => {
+            (first.is_ascii_alphabetic() || *first == b'_')
+                && rest.iter().all(|b| b.is_ascii_alphanumeric() || *b == b'_')
+        }
+        None => false,
+    }
+}
+
+fn parse_env_output(buf: &[u8]) -> EnvBlock {
+    let mut out: Vec<u8> = Vec::with_capacity(buf.len());
+    let mut lines = lines(buf).peekable();
+    while let Some(line) = lines.next() {
+        // the output ends with a newline, which isn't part of the last value
+        if line.is_empty() && lines.peek().is_none() {
+            break;
+        }
+        if !starts_entry(line) {
+            if out.is_empty() {
+                // garbage before the first entry
+                continue;
+            }
+            // continuation of a multi-line value, replace the previous NUL with a newline
+            out.pop();
+            out.push(b'\n');
+        }
+        out.extend_from_slice(line);
+        out.push(b'\0');
+    }
+    EnvBlock::new(out)
+}
+
+fn dotenv_error(line: usize, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {line}: {msg}"))
+}
+
+/// Parse a `.env` file.
+///
+/// Supports `#` comments, blank lines, an optional `export` before the name, whitespace around
+/// `=`, and three kinds of values:
+///  * unquoted, which end at the end of the line or at a ` #` comment, with surrounding
+///    whitespace trimmed
+///  * single-quoted, which are completely literal and can span multiple lines
+///  * double-quoted, which can span multiple lines and support the escapes `\n`, `\r`, `\t`,
+///    `\"`, `\\`, and `\$`
+///
+/// Variable references like `${VAR}` are not expanded.
+fn parse_dotenv(buf: &[u8]) -> io::Result<EnvBlock> {
+    let mut out = Vec::with_capacity(buf.len());
+    let mut pos = 0;
+    let mut line = 1;
+
+    let is_space = |b: u8| b == b' ' || b == b'\t' || b == b'\r';
+    let skip_spaces = |pos: &mut usize| {
+        while *pos < buf.len() && is_space(buf[*pos]) {
+            *pos += 1;
+        }
+    };
+
+    while pos < buf.len() {
+        skip_spaces(&mut pos);
+        if pos >= buf.len() {
+            break;
+        }
+        // blank lines and comments
+        if buf[pos] == b'\n' || buf[pos] == b'#' {
+            while pos < buf.len() && buf[pos] != b'\n' {
+                pos += 1;
+            }
+            pos += 1;
+            line += 1;
+            continue;
+        }
+
+        if buf[pos..].starts_with(b"export") && buf.get(pos + 6).is_some_and(|b| is_space(*b)) {
+            pos += 6;
+            skip_spaces(&mut pos);
+        }
+
+        let key_start = pos;
+        while pos < buf.len() && buf[pos] != b'=' && buf[pos] != b'\n' && !is_space(buf[pos]) {
+            pos += 1;
+        }
+        let key = &buf[key_start..pos];
+        skip_spaces(&mut pos);
+        if key.is_empty() || buf.get(pos) != Some(&b'=') {
+            return Err(dotenv_error(line, "expected NAME=value"));
+        }
+        pos += 1;
+        skip_spaces(&mut pos);
+
+        let start_line = line;
+        let mut value = Vec::new();
+        match buf.get(pos) {
+            Some(b'\'') => {
+                pos += 1;
+                let len = buf[pos..]
+                    .iter()
+                    .position(|b| *b == b'\'')
+                    .ok_or_else(|| dotenv_error(start_line, "unterminated single quote"))?;
+                value.extend_from_slice(&buf[pos..pos + len]);
+                line += value.iter().filter(|b| **b == b'\n').count();
+                pos += len + 1;
+            }
+            Some(b'"') => {
+                pos += 1;
+                loop {
+                    match buf.get(pos) {
+                        None => return Err(dotenv_error(start_line, "unterminated double quote")),
+                        Some(b'"') => break,
+                        Some(b'\\') => {
+                            pos += 1;
+                            match buf.get(pos) {
+                                Some(b'n') => value.push(b'\n'),
+                                Some(b'r') => value.push(b'\r'),
+                                Some(b't') => value.push(b'\t'),
+                                Some(c @ (b'"' | b'\\' | b'$')) => value.push(*c),
+                                // unknown escapes are kept as-is
+                                Some(c) => value.extend_from_slice(&[b'\\', *c]),
+                                None => {
+                                    return Err(dotenv_error(
+                                        start_line,
+                                        "unterminated double quote",
+                                    ));
+                                }
+                            }
+                        }
+                        Some(c) => {
+                            if *c == b'\n' {
+                                line += 1;
+                            }
+                            value.push(*c);
+                        }
+                    }
+                    pos += 1;
+                }
+                pos += 1;
+            }
+            _ => {
+                let start = pos;
+                while pos < buf.len() && buf[pos] != b'\n' {
+                    // a comment needs whitespace before it, so that `a=b#c` keeps its '#'. The
+                    // whitespace may be before `start` since it's already been skipped, but
+                    // there's always at least the '=' there.
+                    if buf[pos] == b'#' && is_space(buf[pos - 1]) {
+                        break;
+                    }
+                    pos += 1;
+                }
+                let mut end = pos;
+                while end > start && is_space(buf[end - 1]) {
+                    end -= 1;
+                }
+                value.extend_from_slice(&buf[start..end]);
+            }
+        }
+
+        // only whitespace or a comment can follow the value
+        skip_spaces(&mut pos);
+        match buf.get(pos) {
+            None | Some(b'\n') => (),
+            Some(b'#') => {
+                while pos < buf.len() && buf[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            Some(_) => return Err(dotenv_error(line, "unexpected text after value")),
+        }
+        pos += 1;
+        line += 1;
+
+        push_entry(&mut out, key, &value);
+    }
+    Ok(EnvBlock::new(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse `input` and collect the entries as lossy `key=value` strings
+    fn parse(format: InputFormat, input: &str) -> Vec<String> {
+        format
+            .parse(input.as_bytes().to_vec())
+            .unwrap()
+            .entries()
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    fn dotenv(input: &str) -> Vec<String> {
+        parse(InputFormat::Dotenv, input)
+    }
+
+    fn dotenv_err(input: &str) -> String {
+        InputFormat::Dotenv
+            .parse(input.as_bytes().to_vec())
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn newline() {
+        assert_eq!(
+            parse(InputFormat::Newline, "A=1\r\n\nB=2\nC=\n"),
+            ["A=1", "B=2", "C="]
+        );
+    }
+
+    #[test]
+    fn env_output() {
+        assert_eq!(
+            parse(
+                InputFormat::EnvOutput,
+                "garbage\nA=1\nB=two\nlines\n\nBASH_FUNC_f%%=() {  true\n}\nC=\n"
+            ),
+            ["A=1", "B=two\nlines\n", "BASH_FUNC_f%%=() {  true\n}", "C="]
+        );
+        // CRLF line endings
+        assert_eq!(
+            parse(InputFormat::EnvOutput, "A=1\r\nB=2\r\n"),
+            ["A=1", "B=2"]
+        );
+    }
+
+    #[test]
+    fn dotenv_unquoted() {
+        assert_eq!(
+            dotenv("A=1\nB = two words  \n  C=\nexport D=4\nexport=5\n"),
+            ["A=1", "B=two words", "C=", "D=4", "export=5"]
+        );
+    }
+
+    #[test]
+    fn dotenv_comments() {
+        assert_eq!(
+            dotenv(
+                "# comment\n\n  # indented\nA=1 # comment\nB=b#c\nC= # comment\nD=#d\nE=\t# tab\n"
+            ),
+            ["A=1", "B=b#c", "C=", "D=#d", "E="]
+        );
+    }
+
+    #[test]
+    fn dotenv_quotes() {
+        assert_eq!(
+            dotenv(
+                "A='single \\n $x'\nB=\"double\"  # comment\nC='multi\nline'\nD=\"two\nlines\"\nE=''\n"
+            ),
+            [
+                "A=single \\n $x",
+                "B=double",
+                "C=multi\nline",
+                "D=two\nlines",
+                "E="
+            ]
+        );
+    }
+
+    #[test]
+    fn dotenv_escapes() {
+        assert_eq!(dotenv(r#"A="\n\r\t\"\\\$\q""#), ["A=\n\r\t\"\\$\\q"]);
+    }
+
+    #[test]
+    fn dotenv_crlf() {
+        assert_eq!(
+            dotenv("A=1\r\nB=\"2\"\r\n# c\r\nC='3' # c\r\n"),
+            ["A=1", "B=2", "C=3"]
+        );
+    }
+
+    #[test]
+    fn dotenv_errors() {
+        assert_eq!(
+            dotenv_err("A=1\nnot an entry\n"),
+            "line 2: expected NAME=value"
+        );
+        assert_eq!(dotenv_err("=1\n"), "line 1: expected NAME=value");
+        assert_eq!(dotenv_err("A='1\n"), "line 1: unterminated single quote");
+        assert_eq!(
+            dotenv_err("A=1\nB=\"2\\\""),
+            "line 2: unterminated double quote"
+        );
+        assert_eq!(
+            dotenv_err("A='1\n2'\nB='x' y\n"),
+            "line 3: unexpected text after value"
+        );
+    }
+}
@@ -0,0 +1,32 @@
+//! Parse and filter environment blocks, i.e. files of the format `<name>=<value>\0` such as
+//! `/proc/<pid>/environ`.
+//!
+//! This is the library half of the `envcat` command-line tool, so that other tools can read and
+//! filter environments exactly the same way.
+//!
+//! ```no_run
+//! use envcat::{EnvBlock, Filter, PatternBuilder};
+//!
+//! let block = EnvBlock::from_pid(1)?;
+//! let pattern = PatternBuilder::new().glob(true).add("LC_*").build()?;
+//! for entry in block.entries().filter(|e| pattern.matches(e)) {
+//!     println!("{}", entry);
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+mod block;
+pub mod coredump;
+mod elf;
+pub mod input;
+#[cfg(target_os = "linux")]
+pub mod live;
+pub mod oci;
+mod pattern;
+pub mod process;
+pub mod systemd;
+
+pub use block::{Entries, EnvBlock, EnvEntry, EnvReader};
+pub use input::InputFormat;
+pub use pattern::{Filter, Pattern, PatternBuilder, PatternError};
+pub use process::Process;
@@ -0,0 +1,113 @@
+//! Diagnostics for environment blocks which parse, but probably not the way they were meant to
+
+use std::collections::HashMap;
+use std::fmt;
+
+use envcat::{EnvBlock, EnvEntry, Filter};
+
+use crate::shell;
+
+/// Something wrong with an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    /// There's no `=`, so the whole entry is the name and the value is empty
+    NoEquals,
+    /// The name is empty, i.e. the entry starts with `=`
+    EmptyName,
+    /// The name can't be used as a shell variable
+    InvalidName,
+    /// The name isn't valid UTF-8
+    NonUtf8Name,
+    /// The value isn't valid UTF-8
+    NonUtf8Value,
+    /// The value ends with spaces, tabs, or newlines, which are easy to miss
+    TrailingWhitespace,
+    /// The name was already defined by the entry with this index, which is the one `getenv`
+    /// returns
+    Duplicate(usize),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoEquals => f.write_str("no '=', treated as a name with an empty value"),
+            Self::EmptyName => f.write_str("empty name"),
+            Self::InvalidName => f.write_str("name isn't a valid shell identifier"),
+            Self::NonUtf8Name => f.write_str("name isn't valid UTF-8"),
+            Self::NonUtf8Value => f.write_str("value isn't valid UTF-8"),
+            Self::TrailingWhitespace => f.write_str("value has trailing whitespace"),
+            Self::Duplicate(first) => write!(
+                f,
+                "duplicate name, getenv() returns the value from entry {first}"
+            ),
+        }
+    }
+}
+
+/// A problem with the entry at `index`, counting from 1
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub index: usize,
+    pub name: String,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry {}", self.index)?;
+        if !self.name.is_empty() {
+            write!(f, " ({})", self.name)?;
+        }
+        write!(f, ": {}", self.problem)
+    }
+}
+
+/// Check the entries of `block` which match `filter`
+pub fn lint(block: &EnvBlock, filter: &impl Filter) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    // index of the first entry with each name
+    let mut first: HashMap<&[u8], usize> = HashMap::new();
+
+    // split the raw buffer rather than using entries() to tell whether there was an '='
+    let chunks = block.as_bytes().split(|b| *b == b'\0');
+    for (chunk, index) in chunks.filter(|c| !c.is_empty()).zip(1..) {
+        let entry = EnvEntry::parse(chunk);
+        if !filter.matches(&entry) {
+            continue;
+        }
+        let mut report = |problem| {
+            findings.push(Finding {
+                index,
+                name: String::from_utf8_lossy(entry.key)
+                    .escape_debug()
+                    .to_string(),
+                problem,
+            })
+        };
+
+        if !chunk.contains(&b'=') {
+            report(Problem::NoEquals);
+        }
+        if entry.key.is_empty() {
+            report(Problem::EmptyName);
+        } else if !shell::is_identifier(entry.key) {
+            report(Problem::InvalidName);
+        }
+        if std::str::from_utf8(entry.key).is_err() {
+            report(Problem::NonUtf8Name);
+        }
+        if std::str::from_utf8(entry.value).is_err() {
+            report(Problem::NonUtf8Value);
+        }
+        if entry.value.last().is_some_and(u8::is_ascii_whitespace) {
+            report(Problem::TrailingWhitespace);
+        }
+        match first.get(entry.key) {
+            Some(&first) => report(Problem::Duplicate(first)),
+            None => {
+                first.insert(entry.key, index);
+            }
+        }
+    }
+    findings
+}
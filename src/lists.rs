@@ -0,0 +1,108 @@
+//! Splitting of PATH-like variables into their elements
+
+use std::collections::HashSet;
+use std::fmt;
+
+use envcat::{Pattern, PatternBuilder, PatternError};
+
+/// Globs for variable names which usually hold colon-separated lists, matched case-insensitively.
+pub const LIST_KEYS: &[&str] = &[
+    "PATH",
+    "LD_LIBRARY_PATH",
+    "PYTHONPATH",
+    "MANPATH",
+    "INFOPATH",
+    "CLASSPATH",
+    "PKG_CONFIG_PATH",
+    "XDG_*_DIRS",
+];
+
+/// Something that looks wrong with a list element
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    /// An empty element, which usually means the current directory
+    Empty,
+    /// The same as an earlier element, so it has no effect
+    Duplicate,
+    /// The path doesn't exist on this machine
+    Missing,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Empty => "empty",
+            Self::Duplicate => "duplicate",
+            Self::Missing => "missing",
+        })
+    }
+}
+
+/// One element of a list variable
+#[derive(Debug, Clone, Copy)]
+pub struct Element<'a> {
+    pub value: &'a [u8],
+    pub problem: Option<Problem>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ListSplitter {
+    keys: Pattern,
+}
+
+impl ListSplitter {
+    /// Split [`LIST_KEYS`] plus the globs in `extra`
+    pub fn new<I, S>(extra: I) -> Result<Self, PatternError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let keys = PatternBuilder::new()
+            .glob(true)
+            .extend(LIST_KEYS.iter().copied())
+            .extend(extra)
+            .build()?;
+        Ok(Self { keys })
+    }
+
+    /// Split the value of `key` into its elements, or `None` if it isn't a list variable.
+    ///
+    /// Elements are checked against the local filesystem, relative paths are relative to
+    /// envcat's working directory.
+    pub fn split<'a>(&self, key: &[u8], value: &'a [u8]) -> Option<Vec<Element<'a>>> {
+        if value.is_empty() || !self.keys.is_match(key) {
+            return None;
+        }
+        let mut seen = HashSet::new();
+        let elements = value
+            .split(|b| *b == b':')
+            .map(|value| {
+                let problem = if value.is_empty() {
+                    Some(Problem::Empty)
+                } else if !seen.insert(value) {
+                    Some(Problem::Duplicate)
+                } else if !exists(value) {
+                    Some(Problem::Missing)
+                } else {
+                    None
+                };
+                Element { value, problem }
+            })
+            .collect();
+        Some(elements)
+    }
+}
+
+fn exists(path: &[u8]) -> bool {
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        std::path::Path::new(OsStr::from_bytes(path)).exists()
+    }
+
+    #[cfg(not(unix))]
+    {
+        std::path::Path::new(&*String::from_utf8_lossy(path)).exists()
+    }
+}
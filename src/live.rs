@@ -0,0 +1,310 @@
+//! Read the current environment of a running process.
+//!
+//! `/proc/<pid>/environ` only shows the environment block that the process started with,
+//! changes made with `setenv()` afterwards go to a new array which libc points `environ` to. To
+//! see those, we look up the `__environ` symbol in the executable or else the libc that the
+//! process has mapped, then read the pointer array and strings out of the process' memory with
+//! `process_vm_readv`. This needs the same permissions as attaching a debugger.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+
+use crate::EnvBlock;
+use crate::elf::{Elf, PT_LOAD, invalid};
+
+/// Give up on arrays and strings longer than this, in case we're reading garbage
+const MAX_LEN: usize = 1 << 20;
+const PAGE_SIZE: u64 = 4096;
+
+/// Names of the environment pointer, in the order to look for them
+const SYMBOLS: &[&str] = &["__environ", "environ"];
+
+/// Read the current environment of process `pid`
+pub fn read_live_environ(pid: u32) -> io::Result<EnvBlock> {
+    let maps = fs::read_to_string(format!("/proc/{pid}/maps"))?;
+
+    // An executable which uses `environ` directly usually gets a copy relocation, so the live
+    // pointer is in the executable's own data and the one in libc is never updated. Look there
+    // first, and only fall back to libc if the executable doesn't define the symbol.
+    let exe = fs::read_link(format!("/proc/{pid}/exe"))?;
+    let exe = exe.to_string_lossy();
+    let deleted = format!("{exe} (deleted)");
+    let mut found = None;
+    if let Some((_, start)) = find_mapping(&maps, |path| path == exe || path == deleted) {
+        found = find_symbol(format!("/proc/{pid}/exe"), start)?;
+    }
+    if found.is_none() {
+        let (path, start) = find_libc(&maps)?;
+        // open libc through the process' root in case it's in a container
+        found = find_symbol(format!("/proc/{pid}/root{path}"), start)?;
+        if found.is_none() {
+            return Err(invalid(format!("no environ symbol in {path}")));
+        }
+    }
+    let (addr, word_size) = found.unwrap();
+
+    let mem = Memory { pid, word_size };
+    let mut ptr = mem.read_word(addr)?;
+    let mut buf = Vec::new();
+    if ptr == 0 {
+        // clearenv() was called, or we found the wrong pointer. Don't print an empty environment
+        // if it's likely to be the latter.
+        if EnvBlock::from_pid(pid)?.entries().next().is_some() {
+            return Err(invalid(
+                "environ is NULL, but the process started with a non-empty environment",
+            ));
+        }
+        return Ok(EnvBlock::new(buf));
+    }
+    for _ in 0..MAX_LEN {
+        let entry = mem.read_word(ptr)?;
+        if entry == 0 {
+            return Ok(EnvBlock::new(buf));
+        }
+        buf.extend_from_slice(&mem.read_cstr(entry)?);
+        buf.push(b'\0');
+        ptr += mem.word_size as u64;
+    }
+    Err(invalid("environ array is too long"))
+}
+
+/// Look up the environment pointer in the ELF file at `path`, which is mapped at `start` in the
+/// process. Returns its address in the process and the process' word size, or `None` if the file
+/// doesn't define it.
+fn find_symbol(path: String, start: u64) -> io::Result<Option<(u64, usize)>> {
+    let mut elf = Elf::new(BufReader::new(File::open(&path)?))?;
+    let mut symbol = None;
+    for name in SYMBOLS {
+        symbol = elf.dynamic_symbol(name)?;
+        if symbol.is_some() {
+            break;
+        }
+    }
+    let Some(symbol) = symbol else {
+        return Ok(None);
+    };
+    let first_vaddr = elf
+        .segments
+        .iter()
+        .filter(|seg| seg.p_type == PT_LOAD)
+        .map(|seg| seg.vaddr)
+        .min()
+        .ok_or_else(|| invalid(format!("no loadable segments in {path}")))?;
+    // the bias is 0 for non-PIE executables, which are mapped at their link address
+    let addr = start
+        .checked_sub(first_vaddr & !(PAGE_SIZE - 1))
+        .and_then(|bias| bias.checked_add(symbol))
+        .ok_or_else(|| invalid(format!("{path} doesn't match where it's mapped")))?;
+    Ok(Some((addr, elf.word_size())))
+}
+
+/// Find the path and start address of the first mapping from offset 0 of a file whose path
+/// matches `is_match` in the contents of `/proc/<pid>/maps`
+fn find_mapping(maps: &str, is_match: impl Fn(&str) -> bool) -> Option<(&str, u64)> {
+    for line in maps.lines() {
+        // start-end perms offset dev inode path
+        let mut fields = line.splitn(6, ' ');
+        let (Some(range), Some(_), Some(offset), Some(_), Some(_), Some(path)) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        ) else {
+            continue;
+        };
+        let path = path.trim_start();
+        if !is_match(path) || u64::from_str_radix(offset, 16) != Ok(0) {
+            continue;
+        }
+        let start = range.split('-').next().unwrap_or_default();
+        return u64::from_str_radix(start, 16)
+            .ok()
+            .map(|start| (path, start));
+    }
+    None
+}
+
+/// Find the path and start address of the libc mapping
+fn find_libc(maps: &str) -> io::Result<(String, u64)> {
+    let is_libc = |path: &str| {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        name.starts_with("libc.so")
+            || (name.starts_with("libc-") && name.ends_with(".so"))
+            || name.starts_with("ld-musl-")
+    };
+    find_mapping(maps, is_libc)
+        .map(|(path, start)| (path.to_owned(), start))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "no libc mapped in the process (statically linked?)",
+            )
+        })
+}
+
+/// Memory of another process
+struct Memory {
+    pid: u32,
+    word_size: usize,
+}
+
+impl Memory {
+    /// Read into `buf`, returning the number of bytes read, which is short if the range runs
+    /// into unmapped memory
+    fn read(&self, addr: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let local = libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        };
+        let remote = libc::iovec {
+            iov_base: addr as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        // SAFETY: the local iovec is exactly `buf`, which we have exclusive access to. The
+        // remote one is only checked by the kernel, and never dereferenced here.
+        let ret =
+            unsafe { libc::process_vm_readv(self.pid as libc::pid_t, &local, 1, &remote, 1, 0) };
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret as usize)
+        }
+    }
+
+    fn read_word(&self, addr: u64) -> io::Result<u64> {
+        let mut buf = [0u8; 8];
+        let buf = &mut buf[..self.word_size];
+        if self.read(addr, buf)? != buf.len() {
+            return Err(invalid(format!("short read at {addr:#x}")));
+        }
+        Ok(if self.word_size == 8 {
+            u64::from_ne_bytes(buf.try_into().unwrap())
+        } else {
+            u64::from(u32::from_ne_bytes(buf.try_into().unwrap()))
+        })
+    }
+
+    /// Read a NUL-terminated string, not including the NUL
+    fn read_cstr(&self, mut addr: u64) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut page = [0u8; PAGE_SIZE as usize];
+        while out.len() < MAX_LEN {
+            // don't cross page boundaries, the next page might not be mapped
+            let len = (PAGE_SIZE - addr % PAGE_SIZE) as usize;
+            let n = self.read(addr, &mut page[..len])?;
+            if n == 0 {
+                return Err(invalid(format!("unterminated string at {addr:#x}")));
+            }
+            if let Some(nul) = page[..n].iter().position(|b| *b == 0) {
+                out.extend_from_slice(&page[..nul]);
+                return Ok(out);
+            }
+            out.extend_from_slice(&page[..n]);
+            addr += n as u64;
+        }
+        Err(invalid("environment string is too long"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Write};
+    use std::path::Path;
+    use std::process::{Child, Command, Stdio};
+
+    use super::*;
+
+    /// A child process, killed when dropped
+    struct Guard(Child);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    /// Start `cmd`, wait for it to print a line once it has changed its environment, then read
+    /// its live environment
+    fn spawn_and_read(cmd: &mut Command) -> EnvBlock {
+        let mut child = Guard(
+            cmd.env("ENVCAT_TEST_OLD", "1")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap(),
+        );
+        // the test harness may print its own output first
+        let ready = io::BufReader::new(child.0.stdout.as_mut().unwrap())
+            .lines()
+            .any(|line| line.unwrap() == "ready");
+        assert!(ready, "child exited without getting ready");
+        read_live_environ(child.0.id()).unwrap()
+    }
+
+    fn check(env: &EnvBlock) {
+        let entries: Vec<String> = env.entries().map(|e| e.to_string()).collect();
+        assert!(entries.iter().any(|e| e == "ENVCAT_TEST_OLD=1"));
+        assert!(entries.iter().any(|e| e == "ENVCAT_TEST_NEW=2"));
+    }
+
+    #[test]
+    fn rust_child() {
+        // the test binary runs itself again with just this test to get a child process
+        if std::env::var_os("ENVCAT_TEST_CHILD").is_some() {
+            // SAFETY: the child runs with a single test thread
+            unsafe { std::env::set_var("ENVCAT_TEST_NEW", "2") };
+            println!("ready");
+            io::stdout().flush().unwrap();
+            // wait for the parent to close stdin or kill us
+            let _ = io::stdin().lines().count();
+            return;
+        }
+        let env = spawn_and_read(
+            Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "live::tests::rust_child", "--nocapture"])
+                .args(["--test-threads", "1", "-q"])
+                .env("ENVCAT_TEST_CHILD", "1"),
+        );
+        check(&env);
+    }
+
+    /// A C program which uses `environ` directly, so the executable gets a copy relocation of it
+    /// and the pointer in libc is stale
+    #[test]
+    fn c_child_copy_relocation() {
+        const SOURCE: &str = r#"
+            #include <stdio.h>
+            #include <stdlib.h>
+            #include <unistd.h>
+            extern char **environ;
+            int main(void) {
+                char c;
+                setenv("ENVCAT_TEST_NEW", "2", 1);
+                printf("%s\n", environ[0] ? "ready" : "empty");
+                fflush(stdout);
+                while (read(0, &c, 1) > 0) {}
+                return 0;
+            }
+        "#;
+        let dir = std::env::temp_dir().join(format!("envcat-live-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("child.c");
+        let exe = dir.join("child");
+        fs::write(&src, SOURCE).unwrap();
+        let compiled = Command::new(std::env::var_os("CC").unwrap_or("cc".into()))
+            .arg("-o")
+            .args([Path::new(&exe), &src])
+            .status();
+        if !compiled.is_ok_and(|status| status.success()) {
+            let _ = fs::remove_dir_all(&dir);
+            eprintln!("skipping, no working C compiler");
+            return;
+        }
+        let env = spawn_and_read(&mut Command::new(&exe));
+        let _ = fs::remove_dir_all(&dir);
+        check(&env);
+    }
+}
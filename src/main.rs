@@ -1,102 +1,767 @@
-use std::io::{self, Read, Write};
+mod lint;
+mod lists;
+mod output;
+mod redact;
+mod shell;
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write as _};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
 
-use anstyle::{AnsiColor, Style};
 use anyhow::Context as _;
-use clap::Parser;
-use globset::{GlobBuilder, GlobSet};
-use regex::bytes::{RegexSet, RegexSetBuilder};
+use clap::builder::{PossibleValuesParser, TypedValueParser as _};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use envcat::{EnvBlock, EnvEntry, EnvReader, Filter, InputFormat, PatternBuilder, Process};
+use regex::Regex;
 
-const STYLE_KEY: Style = color_style(AnsiColor::Green);
-const STYLE_EQU: Style = color_style(AnsiColor::Blue);
-const STYLE_VAL: Style = Style::new();
+use lists::ListSplitter;
+use output::{Format, Printer};
+use redact::{RedactMode, Redactor};
 
-const fn color_style(color: AnsiColor) -> Style {
-    Style::new().fg_color(Some(anstyle::Color::Ansi(color)))
+/// Where to read an environment block from
+enum Source {
+    /// envcat's own environment
+    Current,
+    Stdin,
+    File(String),
+    Pid(u32),
+    LivePid(u32),
+    Thread(u32, u32),
+    Core(String),
+    SystemdUnit(String),
+    Oci(String),
 }
 
-/// `GlobSet` only matches on `AsRef<Path>` types, extend it to accept arbitrary bytes as input too,
-/// since we're using it to match key names rather than file paths.
-trait GlobExt {
-    fn is_match_bytes(&self, needle: &[u8]) -> bool;
+type StreamReader = EnvReader<Box<dyn BufRead>>;
+
+impl Source {
+    /// Interpret a FILE argument, which is a PID when `pid` is set. `None` or `-` means stdin.
+    /// `live` reads the current rather than initial environment of a PID.
+    fn new(arg: Option<&str>, pid: bool, live: bool) -> anyhow::Result<Self> {
+        if pid {
+            let pid = parse_pid(arg.expect("pid option but no file"))?;
+            return Ok(if live {
+                Self::LivePid(pid)
+            } else {
+                Self::Pid(pid)
+            });
+        }
+        Ok(match arg {
+            Some("-") | None => Self::Stdin,
+            Some(path) => Self::File(path.into()),
+        })
+    }
+
+    /// Open this source for reading entries one at a time, if it's a stream (stdin or a file)
+    /// rather than something that has to be read in one go. Also returns a description of the
+    /// source for error messages.
+    fn stream(&self) -> anyhow::Result<Option<(StreamReader, String)>> {
+        Ok(match self {
+            Self::Stdin => Some((EnvReader::new(Box::new(io::stdin().lock())), "stdin".into())),
+            Self::File(path) => {
+                let file = File::open(path).with_context(|| format!("failed to open {path}"))?;
+                // regular files end, so long entries can be buffered like without streaming. The
+                // limit is only needed for FIFOs and the like.
+                let is_file = file.metadata().is_ok_and(|meta| meta.is_file());
+                let mut reader = EnvReader::new(Box::new(BufReader::new(file)) as Box<dyn BufRead>);
+                if is_file {
+                    reader = reader.max_entry_len(None);
+                }
+                Some((reader, path.clone()))
+            }
+            _ => None,
+        })
+    }
+
+    /// Read the environment block from this source. Files and stdin are parsed according to
+    /// `format`, everything else is always NUL-separated.
+    fn read(&self, format: InputFormat) -> anyhow::Result<EnvBlock> {
+        match self {
+            #[cfg(unix)]
+            Self::Current => {
+                use std::os::unix::ffi::OsStrExt;
+                let mut buf = Vec::new();
+                for (key, value) in std::env::vars_os() {
+                    buf.extend_from_slice(key.as_bytes());
+                    buf.push(b'=');
+                    buf.extend_from_slice(value.as_bytes());
+                    buf.push(b'\0');
+                }
+                Ok(EnvBlock::new(buf))
+            }
+            #[cfg(not(unix))]
+            Self::Current => {
+                anyhow::bail!("reading envcat's own environment is only supported on unix")
+            }
+            Self::Stdin => {
+                let mut buf = Vec::new();
+                io::stdin()
+                    .lock()
+                    .read_to_end(&mut buf)
+                    .context("failed to read stdin")?;
+                format.parse(buf).context("failed to parse stdin")
+            }
+            Self::File(path) => {
+                let buf = std::fs::read(path).with_context(|| format!("failed to read {path}"))?;
+                format
+                    .parse(buf)
+                    .with_context(|| format!("failed to parse {path}"))
+            }
+            Self::Pid(pid) => EnvBlock::from_pid(*pid)
+                .with_context(|| format!("failed to read /proc/{pid}/environ")),
+            Self::Thread(pid, tid) => Process::new(*pid)
+                .thread_environ(*tid)
+                .with_context(|| format!("failed to read /proc/{pid}/task/{tid}/environ")),
+            #[cfg(target_os = "linux")]
+            Self::LivePid(pid) => Process::new(*pid)
+                .live_environ()
+                .with_context(|| format!("failed to read the live environment of PID {pid}")),
+            #[cfg(not(target_os = "linux"))]
+            Self::LivePid(_) => anyhow::bail!("--live is only supported on Linux"),
+            Self::Core(path) => EnvBlock::from_core_file(path)
+                .with_context(|| format!("failed to read environment from core dump {path}")),
+            Self::SystemdUnit(unit) => {
+                let path = envcat::systemd::find_unit(unit)
+                    .with_context(|| format!("unit {unit} not found"))?;
+                // the unit's own name rather than the file's, which is a template for instances
+                let name = Path::new(unit)
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or(unit);
+                envcat::systemd::read_unit_as(&path, name)
+                    .with_context(|| format!("failed to read unit {}", path.display()))
+            }
+            Self::Oci(path) => envcat::oci::read_path(path)
+                .with_context(|| format!("failed to read container config {path}")),
+        }
+    }
 }
 
-impl GlobExt for GlobSet {
-    fn is_match_bytes(&self, needle: &[u8]) -> bool {
-        // On unix, Path and OsStr are interchangeable with `&[u8]`
-        #[cfg(unix)]
-        {
-            use std::ffi::OsStr;
-            use std::os::unix::ffi::OsStrExt;
-            self.is_match(OsStr::from_bytes(needle))
+fn parse_pid(arg: &str) -> anyhow::Result<u32> {
+    arg.parse()
+        .with_context(|| format!("failed to parse PID argument '{arg}' as integer"))
+}
+
+/// Find processes whose comm or command line matches `re`, not including this one
+fn find_processes(re: &Regex) -> anyhow::Result<Vec<Process>> {
+    let mut procs = Vec::new();
+    for pid in envcat::process::pids().context("failed to list processes")? {
+        if pid == std::process::id() {
+            continue;
+        }
+        let proc = Process::new(pid);
+        // processes can exit while we're scanning, skip anything we can't read
+        let Ok(comm) = proc.comm() else { continue };
+        if is_match(&proc, &comm, re) {
+            procs.push(proc);
         }
+    }
+    Ok(procs)
+}
+
+/// Whether `re` matches a process' `comm` or command line
+fn is_match(proc: &Process, comm: &str, re: &Regex) -> bool {
+    re.is_match(comm) || proc.command_line().is_ok_and(|cmd| re.is_match(&cmd))
+}
+
+/// How often `--watch` scans for new processes
+const WATCH_INTERVAL: Duration = Duration::from_millis(50);
 
-        // Otherwise (e.g. on windows) we can't directly interchange bytes and OsStr, so loop through
-        // UTF-8 to get a string that implements AsRef<Path>
-        #[cfg(not(unix))]
-        {
-            let s = String::from_utf8_lossy(needle);
-            self.is_match(&*s)
+/// Print the environment of every process that starts (or execs) from now on and matches `re`,
+/// until interrupted.
+///
+/// Processes are found by polling `/proc`, so very short-lived ones can still be missed. A
+/// process is considered new when its PID appears or its comm changes, the latter catching a
+/// fork followed by exec.
+fn watch<W: io::Write>(
+    printer: &mut Printer<W>,
+    re: &Regex,
+    filter: &impl Filter,
+    order: Order,
+) -> anyhow::Result<()> {
+    let own_pid = std::process::id();
+    let mut known: HashMap<u32, String> = HashMap::new();
+    let mut first = true;
+    loop {
+        let pids = envcat::process::pids().context("failed to list processes")?;
+        known.retain(|pid, _| pids.binary_search(pid).is_ok());
+        for pid in pids {
+            let proc = Process::new(pid);
+            let Ok(comm) = proc.comm() else { continue };
+            if pid == own_pid || known.get(&pid) == Some(&comm) {
+                continue;
+            }
+            let matched = !first && is_match(&proc, &comm, re);
+            known.insert(pid, comm);
+            if !matched {
+                continue;
+            }
+            // it may have exited already, or belong to another user
+            let Ok(block) = proc.environ() else { continue };
+            let cmdline = proc.command_line().unwrap_or_default();
+            let detail = format!("{} {cmdline}", output::timestamp(SystemTime::now()));
+            printer.begin_section(&pid.to_string(), Some(&detail))?;
+            print_block(printer, &block, filter, order)?;
+            printer.flush()?;
         }
+        first = false;
+        std::thread::sleep(WATCH_INTERVAL);
     }
 }
 
-enum Pattern {
-    Empty,
-    Glob(GlobSet),
-    Regex(RegexSet),
+/// What to sort the printed entries by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum SortKey {
+    /// Variable name
+    Name,
+    /// Variable value
+    Value,
+    /// Length of the value
+    Length,
+    /// Keep the original order
+    #[default]
+    None,
 }
 
-impl Pattern {
-    fn is_match(&self, name: &[u8]) -> bool {
-        match self {
-            Self::Empty => true,
-            Self::Glob(globs) => globs.is_match_bytes(name),
-            Self::Regex(regexes) => regexes.is_match(name),
+/// How to order the printed entries
+#[derive(Debug, Clone, Copy)]
+struct Order {
+    sort: SortKey,
+    reverse: bool,
+    /// drop entries whose name already appeared earlier in the block, for formats which can't
+    /// represent duplicates
+    unique: bool,
+}
+
+/// Get the entries of `block` which should be printed, along with their original index counting
+/// from 1. Sorting is stable, so entries with equal sort keys stay in their original order even
+/// when reversed.
+fn select<'a>(
+    block: &'a EnvBlock,
+    filter: &impl Filter,
+    order: Order,
+) -> Vec<(usize, EnvEntry<'a>)> {
+    let mut data: Vec<_> = block
+        .entries()
+        .zip(1..)
+        .filter(|(e, _)| filter.matches(e))
+        .map(|(e, i)| (i, e))
+        .collect();
+    if order.unique {
+        // before sorting, so that the first one is kept like getenv() returns
+        let mut seen = HashSet::new();
+        data.retain(|(_, e)| seen.insert(e.key));
+    }
+    let cmp = |(_, a): &(usize, EnvEntry), (_, b): &(usize, EnvEntry)| match order.sort {
+        SortKey::Name => a.key.cmp(b.key),
+        SortKey::Value => a.value.cmp(b.value),
+        SortKey::Length => a.value.len().cmp(&b.value.len()),
+        SortKey::None => std::cmp::Ordering::Equal,
+    };
+    match (order.sort, order.reverse) {
+        (SortKey::None, false) => (),
+        (SortKey::None, true) => data.reverse(),
+        (_, false) => data.sort_by(cmp),
+        (_, true) => data.sort_by(|a, b| cmp(b, a)),
+    }
+    data
+}
+
+/// Print the entries of `block` which match `filter`
+fn print_block<W: io::Write>(
+    printer: &mut Printer<W>,
+    block: &EnvBlock,
+    filter: &impl Filter,
+    order: Order,
+) -> io::Result<()> {
+    for (index, entry) in select(block, filter, order) {
+        printer.write_entry(index, &entry)?;
+    }
+    Ok(())
+}
+
+/// Print the entries from `reader` which match `filter` as they're read, so that envcat can be
+/// used as a filter on an endless stream. The output is flushed after each entry. `name` describes
+/// the input for error messages.
+fn print_stream<W: io::Write>(
+    printer: &mut Printer<W>,
+    mut reader: EnvReader<impl BufRead>,
+    name: &str,
+    filter: &impl Filter,
+) -> anyhow::Result<()> {
+    let mut index = 0;
+    while let Some(entry) = reader
+        .next_entry()
+        .with_context(|| format!("failed to read {name}"))?
+    {
+        index += 1;
+        if filter.matches(&entry) {
+            printer.write_entry(index, &entry)?;
+            printer.flush()?;
         }
     }
+    Ok(())
 }
 
-/// pretty-print a key/value pair to `out`
-fn write_pair<W: Write>(out: &mut W, key: &[u8], val: &[u8]) -> io::Result<()> {
-    // [u8] isn't Display so do it ourselves
-    STYLE_KEY.write_to(out)?;
-    out.write_all(key)?;
-    STYLE_KEY.write_reset_to(out)?;
-    write!(out, "{}={}", STYLE_EQU.render(), STYLE_EQU.render_reset())?;
-    if !val.is_empty() {
-        STYLE_VAL.write_to(out)?;
-        out.write_all(val)?;
-        STYLE_VAL.write_reset_to(out)?;
+/// Print the environment of `proc` relative to `parent`, then recurse into its children one level
+/// deeper. Without a parent, the environment is printed in full.
+fn print_tree<W: io::Write>(
+    printer: &mut Printer<W>,
+    proc: Process,
+    parent: Option<&EnvBlock>,
+    depth: usize,
+    filter: &impl Filter,
+    order: Order,
+) -> anyhow::Result<()> {
+    let block = match proc.environ() {
+        Ok(block) => Some(block),
+        Err(err) => {
+            // keep going with its children, comparing them to the closest readable ancestor
+            eprintln!(
+                "Warning: failed to read environment of PID {}: {err}",
+                proc.pid
+            );
+            None
+        }
+    };
+    printer.set_indent(depth);
+    if let Some(block) = &block {
+        let cmdline = proc.command_line().unwrap_or_default();
+        printer.begin_section(&proc.pid.to_string(), Some(&cmdline))?;
+        match parent {
+            Some(parent) => printer.write_diff(&parent.to_map(filter), &block.to_map(filter))?,
+            None => print_block(printer, block, filter, order)?,
+        }
+    }
+
+    let children = match proc.children() {
+        Ok(children) => children,
+        // the process exited while we were looking at it
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("failed to list children of PID {}", proc.pid));
+        }
+    };
+    // envcat itself shows up when asked for the tree of the shell it was run from
+    for child in children.into_iter().filter(|c| c.pid != std::process::id()) {
+        print_tree(
+            printer,
+            child,
+            block.as_ref().or(parent),
+            depth + 1,
+            filter,
+            order,
+        )?;
     }
-    out.write_all(b"\n")?;
     Ok(())
 }
 
+/// How to show the environments of a process' ancestors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AncestryMode {
+    /// Print every environment in full
+    Full,
+    /// Print only what changed from the parent
+    Diff,
+}
+
+/// Run a command with exactly the filtered environment of another process or file
+#[derive(Debug, clap::Args)]
+struct ExecArgs {
+    /// Where to get the environment from.
+    ///
+    /// A number is a PID, '-' reads stdin, and anything else is a file path (use './123' for a
+    /// file named with digits). Defaults to envcat's own environment.
+    #[arg(long, value_name = "PID|FILE")]
+    from: Option<String>,
+
+    /// Format of --from when it's a file or stdin, see 'envcat --help'
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value_t,
+        value_parser = PossibleValuesParser::new(InputFormat::NAMES)
+            .map(|s| s.parse::<InputFormat>().unwrap()),
+    )]
+    input_format: InputFormat,
+
+    /// Only pass variables whose name matches PATTERN. Can be given more than once.
+    #[arg(short = 'i', long, value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// Don't pass variables whose name matches PATTERN. Can be given more than once.
+    #[arg(short = 'x', long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Only pass variables whose value matches PATTERN. Can be given more than once.
+    #[arg(long, value_name = "PATTERN")]
+    value: Vec<String>,
+
+    /// Patterns are globs instead of regexes
+    #[arg(short, long)]
+    glob: bool,
+
+    /// Patterns are case-sensitive
+    #[arg(short = 's', long)]
+    case_sensitive: bool,
+
+    /// The command to run, and its arguments
+    #[arg(required = true, trailing_var_arg = true, value_name = "COMMAND")]
+    command: Vec<OsString>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run a command with an environment read from a process or file.
+    ///
+    /// The command gets exactly the variables which match the patterns and nothing else, like
+    /// 'env -i' with the variables copied from the source. With duplicate names, the first one
+    /// is used, as getenv() would. COMMAND is looked up in the new environment's PATH.
+    Exec(ExecArgs),
+}
+
+/// Replace envcat with the command from `args`
+fn exec(args: &ExecArgs) -> anyhow::Result<()> {
+    let source = match args.from.as_deref() {
+        None => Source::Current,
+        Some(arg) if !arg.is_empty() && arg.bytes().all(|b| b.is_ascii_digit()) => {
+            Source::new(Some(arg), true, false)?
+        }
+        Some(arg) => Source::new(Some(arg), false, false)?,
+    };
+    let pattern = PatternBuilder::new()
+        .extend(&args.include)
+        .extend_exclude(&args.exclude)
+        .extend_values(&args.value)
+        .glob(args.glob)
+        .case_sensitive(args.case_sensitive)
+        .build()?;
+    let block = source.read(args.input_format)?;
+
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::process::CommandExt;
+
+        let mut cmd = std::process::Command::new(&args.command[0]);
+        cmd.args(&args.command[1..]).env_clear();
+        for (key, value) in block.to_map(&pattern) {
+            cmd.env(OsStr::from_bytes(key), OsStr::from_bytes(value));
+        }
+        // exec only returns on failure
+        let err = cmd.exec();
+        Err(err).with_context(|| format!("failed to execute {}", args.command[0].to_string_lossy()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (pattern, block);
+        anyhow::bail!("exec is only supported on unix")
+    }
+}
+
 /// Pretty-print files of the format `<name>=<value>\0`
 #[derive(Debug, Parser)]
-#[command(version)]
+#[command(version, args_conflicts_with_subcommands = true)]
+#[command(group(ArgGroup::new("source").args(["file", "files", "diff"]).multiple(true)))]
+#[command(group(ArgGroup::new("matcher").args(["pattern", "exclude", "value"]).multiple(true)))]
 struct Args {
     /// FILE is a process' PID instead of a file path.
     ///
     /// This is a shorthand for reading `/proc/<pid>/environ`
-    #[arg(short, long, requires = "file")]
+    #[arg(short, long, requires = "source")]
     pid: bool,
 
-    /// PATTERN is a glob instead of regex
-    #[arg(short, long, requires = "pattern")]
+    /// With --pid, read the process' current environment rather than the one it started with.
+    ///
+    /// This includes changes the process made with setenv() and friends, which aren't visible
+    /// in /proc/<pid>/environ. It works by finding libc's 'environ' variable and reading the
+    /// process' memory, which needs the same permissions as attaching a debugger.
+    #[arg(short, long, requires = "pid")]
+    live: bool,
+
+    /// With --pid, read the environment of thread TID rather than the main thread.
+    #[arg(long, value_name = "TID", requires = "pid", conflicts_with_all = ["diff", "live"])]
+    tid: Option<u32>,
+
+    /// With --pid, read the environment of every thread in the process.
+    ///
+    /// Threads with identical environments are grouped together under one header, so a single
+    /// header means that all threads agree.
+    #[arg(long, requires = "pid", conflicts_with_all = ["diff", "live", "tid"])]
+    all_threads: bool,
+
+    /// With --pid, also print the environment of each of the process' ancestors.
+    ///
+    /// This follows the parent PIDs up to PID 1 and prints them starting from the top. MODE
+    /// 'full' prints every environment in full, 'diff' prints only the first one in full and then
+    /// the changes each process made relative to its parent.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "full",
+        requires = "pid",
+        conflicts_with_all = ["diff", "tid", "all_threads"]
+    )]
+    ancestry: Option<AncestryMode>,
+
+    /// Print the environment of every process whose name or command line matches REGEX.
+    ///
+    /// Each environment is printed under a header with the process' PID and command line. All
+    /// positional arguments are treated as patterns in this mode.
+    #[arg(
+        short,
+        long,
+        visible_alias = "pgrep",
+        value_name = "REGEX",
+        conflicts_with_all = ["pid", "diff"]
+    )]
+    name: Option<String>,
+
+    /// Search the environment of every running process.
+    ///
+    /// Matching variables are printed with the PID of their process in front. Processes which
+    /// can't be read (usually due to permissions) are skipped. All positional arguments are
+    /// treated as patterns in this mode.
+    #[arg(short = 'A', long, conflicts_with_all = ["pid", "diff", "name"])]
+    all_pids: bool,
+
+    /// Print the environment of PID and everything its descendants changed, as a tree.
+    ///
+    /// PID's environment is printed in full, then each child, grandchild, and so on is shown
+    /// indented under its parent with the variables it added ('+'), removed ('-'), or changed
+    /// ('~') relative to that parent. All positional arguments are treated as patterns in this
+    /// mode.
+    #[arg(long, value_name = "PID", conflicts_with_all = ["pid", "diff", "name", "all_pids"])]
+    tree: Option<u32>,
+
+    /// Wait for new processes whose name or command line matches REGEX and print their
+    /// environments as they start.
+    ///
+    /// Each environment is printed under a header with the process' PID, the time it was seen,
+    /// and its command line. This runs until interrupted. /proc is polled, so processes which
+    /// exit within a few milliseconds may be missed. All positional arguments are treated as
+    /// patterns in this mode.
+    #[arg(
+        long,
+        value_name = "REGEX",
+        conflicts_with_all = ["pid", "diff", "name", "all_pids", "tree"]
+    )]
+    watch: Option<String>,
+
+    /// Format of FILE or stdin.
+    ///
+    /// 'nul' is '<name>=<value>' entries separated by NUL bytes, like /proc/<pid>/environ or
+    /// 'env -0'. 'newline' is one entry per line. 'env-output' is the output of 'env' or
+    /// 'printenv', where lines not starting with '<name>=' continue a multi-line value. 'dotenv'
+    /// is a .env file with comments, quoted values, and optional 'export' prefixes.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value_t,
+        value_parser = PossibleValuesParser::new(InputFormat::NAMES)
+            .map(|s| s.parse::<InputFormat>().unwrap()),
+    )]
+    input_format: InputFormat,
+
+    /// Read the environment of a crashed process from its ELF core dump.
+    ///
+    /// This is the initial environment like --pid shows, found on the process' stack. All
+    /// positional arguments are treated as patterns in this mode.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["pid", "diff", "name", "all_pids", "tree", "watch"]
+    )]
+    core: Option<String>,
+
+    /// Show the environment that a systemd unit would get from Environment= and
+    /// EnvironmentFile= settings, including drop-ins.
+    ///
+    /// UNIT is a path to a unit file, or the name of an installed system unit. Variables that
+    /// systemd sets on its own (PATH, INVOCATION_ID, etc.) aren't included. All positional
+    /// arguments are treated as patterns in this mode.
+    #[arg(
+        long,
+        value_name = "UNIT",
+        conflicts_with_all = ["pid", "diff", "name", "all_pids", "tree", "watch", "core"]
+    )]
+    systemd_unit: Option<String>,
+
+    /// Read the environment from a container image or runtime config.
+    ///
+    /// PATH is an OCI image config or 'docker inspect' output ('Env'), an OCI runtime
+    /// config.json ('process.env'), or a directory with an unpacked OCI image layout, 'docker
+    /// save' output, or runtime bundle. All positional arguments are treated as patterns in this
+    /// mode.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = [
+            "pid", "diff", "name", "all_pids", "tree", "watch", "core", "systemd_unit"
+        ]
+    )]
+    oci: Option<String>,
+
+    /// Exclude variables whose name matches PATTERN, even if they match an include pattern.
+    ///
+    /// Uses the same pattern syntax as the include patterns, and can be given more than once.
+    #[arg(short = 'x', long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Only include variables whose value matches PATTERN.
+    ///
+    /// Like the name patterns, this is a case-insensitive regex unless -g/--glob or
+    /// -s/--case-sensitive are used. Can be given more than once to match any of several values.
+    #[arg(long, value_name = "PATTERN")]
+    value: Vec<String>,
+
+    /// PATTERN, --exclude, and --value are globs instead of regexes
+    #[arg(short, long, requires = "matcher")]
     glob: bool,
 
-    /// PATTERN is case-sensitive
-    #[arg(short = 's', long, requires = "pattern")]
+    /// PATTERN, --exclude, and --value are case-sensitive
+    #[arg(short = 's', long, requires = "matcher")]
     case_sensitive: bool,
 
-    /// Sort the list by <name>
-    #[arg(short = 'S', long)]
-    sort: bool,
+    /// Sort the list by KEY.
+    ///
+    /// The sort is stable, so variables with the same KEY stay in their original order. Without
+    /// this option or with 'none', variables are printed in their original order. '-S' alone
+    /// sorts by name.
+    #[arg(
+        short = 'S',
+        long,
+        value_enum,
+        value_name = "KEY",
+        num_args = 0..=1,
+        require_equals = true,
+        default_value_t,
+        default_missing_value = "name",
+        hide_default_value = true
+    )]
+    sort: SortKey,
+
+    /// Reverse the order of the list, after sorting
+    #[arg(short, long)]
+    reverse: bool,
+
+    /// Print each variable's index in the original environment in front of it, counting from 1.
+    ///
+    /// This shows which of several duplicate variables comes first, i.e. which one getenv()
+    /// returns. Only affects text output.
+    #[arg(short = 'N', long)]
+    number: bool,
+
+    /// Compare two environments rather than printing one.
+    ///
+    /// A and B are read like FILE (so they're PIDs when using --pid), and either may be '-' for
+    /// stdin. Variables added in B are marked with '+', removed ones with '-', and changed ones
+    /// are printed twice with '~', old value first. All positional arguments are treated as
+    /// patterns in this mode.
+    #[arg(short, long, num_args = 2, value_names = ["A", "B"])]
+    diff: Option<Vec<String>>,
+
+    /// Output format.
+    ///
+    /// JSON can't represent arbitrary bytes, so names or values which aren't valid UTF-8 are
+    /// printed lossily along with 'key_base64'/'value_base64' fields holding the exact bytes. With
+    /// 'json', such entries map to a '{"key": ..., "value": ...}' object like 'json-lines' uses
+    /// rather than a plain string, and the object key is the base64 of the name if the name isn't
+    /// valid UTF-8.
+    ///
+    /// 'nul' is the same format that envcat reads by default, so it can be piped into another
+    /// envcat or used as an environ file. It's the only format other than JSON which preserves
+    /// every value exactly, including ones with newlines.
+    ///
+    /// The shell formats print statements suitable for 'eval', quoted so that any value is safe.
+    /// Variables whose names aren't valid shell identifiers are skipped with a warning.
+    ///
+    /// 'json' and the shell formats only include the first of several variables with the same
+    /// name, which is the one getenv() returns.
+    #[arg(short, long, value_enum, default_value_t, conflicts_with = "diff")]
+    format: Format,
+
+    /// Hide the values of variables which look like secrets.
+    ///
+    /// Variables with names like '*TOKEN*', '*SECRET*', '*PASSWORD*', '*_KEY', or 'AWS_*', plus
+    /// any --redact-key globs, have their values replaced. MODE 'mask' prints '****', while 'hash'
+    /// adds a short hash of the value so that equal values can be recognized.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "mask"
+    )]
+    redact: Option<RedactMode>,
+
+    /// Also redact variables whose name matches GLOB. Implies --redact.
+    #[arg(long, value_name = "GLOB")]
+    redact_key: Vec<String>,
+
+    /// Print list variables like PATH with one element per line.
+    ///
+    /// PATH, LD_LIBRARY_PATH, PYTHONPATH, MANPATH, INFOPATH, CLASSPATH, PKG_CONFIG_PATH,
+    /// 'XDG_*_DIRS', and any --list-key globs are split on ':'. Elements which are empty,
+    /// duplicates of an earlier one, or don't exist on this machine are marked. Only affects
+    /// text output.
+    #[arg(long)]
+    split_lists: bool,
+
+    /// Also split variables whose name matches GLOB. Implies --split-lists.
+    #[arg(long, value_name = "GLOB")]
+    list_key: Vec<String>,
+
+    /// Check for problems in the environment rather than printing it.
+    ///
+    /// Reports duplicate names (and which one getenv() returns), entries without '=', empty
+    /// names, names that aren't valid shell identifiers, names or values that aren't valid UTF-8,
+    /// and values with trailing whitespace. Entries are numbered from 1 in their original order.
+    /// Exits with an error if anything was found.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "diff", "name", "all_pids", "tree", "watch", "ancestry", "all_threads", "format"
+        ]
+    )]
+    lint: bool,
+
+    /// Label each variable with the file or PID it came from, like 'grep -H', rather than
+    /// printing a header for each one.
+    ///
+    /// Only affects text output, JSON formats always include the source when there's more than
+    /// one.
+    #[arg(short = 'H', long, conflicts_with_all = ["diff", "tree", "ancestry", "lint"])]
+    prefix_source: bool,
+
+    /// Read FILE (or PID with --pid), can be given more than once.
+    ///
+    /// With several inputs, each environment is printed under a header, or see
+    /// --prefix-source. When this is used, all positional arguments are treated as patterns.
+    #[arg(
+        short = 'F',
+        long = "file",
+        value_name = "FILE",
+        conflicts_with_all = [
+            "diff", "name", "all_pids", "tree", "watch", "core", "systemd_unit", "oci"
+        ]
+    )]
+    files: Vec<String>,
 
     /// File path, omit or specify '-' to read stdin.
     ///
-    /// When using --pid, this is a process ID number
+    /// When using --pid, this is a process ID number, or several comma-separated ones. Any
+    /// numeric arguments right after it are more PIDs. Each environment is then printed under a
+    /// header, or see --prefix-source. More files can be given with --file.
     file: Option<String>,
 
     /// Filter the variable names based on patterns
@@ -105,82 +770,342 @@ struct Args {
     /// case-insensitive regexes, but this can be modified using the -g/--glob and
     /// -s/--case-sensitive flags.
     pattern: Option<Vec<String>>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Args {
+    /// Whether the input is set by an option rather than the FILE argument
+    fn has_source_option(&self) -> bool {
+        self.diff.is_some()
+            || self.name.is_some()
+            || self.all_pids
+            || self.tree.is_some()
+            || self.watch.is_some()
+            || self.core.is_some()
+            || self.systemd_unit.is_some()
+            || self.oci.is_some()
+    }
 }
 
 fn run() -> anyhow::Result<()> {
-    let args = Args::parse();
-    let path = if args.pid {
-        let pid = args
-            .file
-            .expect("pid option but no file")
-            .parse::<u32>()
-            .context("failed to parse PID argument as integer")?;
-        Some(format!("/proc/{pid}/environ"))
+    let mut args = Args::parse();
+
+    if let Some(Command::Exec(exec_args)) = &args.command {
+        return exec(exec_args);
+    }
+
+    // when the source is given by an option, there's no FILE argument, so it's really the first
+    // pattern
+    if args.has_source_option()
+        && let Some(file) = args.file.take()
+    {
+        args.pattern.get_or_insert_default().insert(0, file);
+    }
+
+    // with --file, every positional argument is a pattern too
+    if !args.files.is_empty()
+        && let Some(file) = args.file.take()
+    {
+        args.pattern.get_or_insert_default().insert(0, file);
+    }
+
+    // several PIDs can be given as a comma-separated list, and more files or PIDs with --file
+    let mut inputs: Vec<String> = match &args.file {
+        Some(file) if args.pid => file.split(',').map(String::from).collect(),
+        Some(file) => vec![file.clone()],
+        None => Vec::new(),
+    };
+    // with --pid, leading numeric arguments are more PIDs, like 'envcat -p 100 200 JAVA'. A
+    // variable name can't start with a digit, so they'd be useless as patterns anyway.
+    if args.pid
+        && args.file.is_some()
+        && let Some(patterns) = &mut args.pattern
+    {
+        let is_pid = |arg: &String| !arg.is_empty() && arg.bytes().all(|b| b.is_ascii_digit());
+        let n = patterns.iter().take_while(|arg| is_pid(arg)).count();
+        inputs.extend(patterns.drain(..n));
+    }
+    inputs.extend(args.files.iter().cloned());
+    if inputs.iter().filter(|arg| *arg == "-").count() > 1 {
+        anyhow::bail!("stdin can only be read once");
+    }
+    match inputs.as_slice() {
+        [] => (),
+        [input] => args.file = Some(input.clone()),
+        [first, second, ..] => {
+            if args.ancestry.is_some() || args.all_threads || args.tid.is_some() || args.lint {
+                anyhow::bail!(
+                    "only one FILE can be used with --ancestry, --all-threads, --tid, and --lint, \
+                     not {first} and {second}"
+                );
+            }
+        }
+    }
+
+    let order = Order {
+        sort: args.sort,
+        reverse: args.reverse,
+        unique: args.format.unique(),
+    };
+    let pattern = PatternBuilder::new()
+        .extend(args.pattern.iter().flatten())
+        .extend_exclude(&args.exclude)
+        .extend_values(&args.value)
+        .glob(args.glob)
+        .case_sensitive(args.case_sensitive)
+        .build()?;
+
+    let redactor = if args.redact.is_some() || !args.redact_key.is_empty() {
+        Some(Redactor::new(
+            &args.redact_key,
+            args.redact.unwrap_or_default(),
+        )?)
     } else {
-        match args.file.as_deref() {
-            Some("-") | None => None,
-            Some(path) => Some(path.into()),
+        None
+    };
+
+    if let Some(diff) = &args.diff {
+        let old_src = Source::new(Some(&diff[0]), args.pid, args.live)?;
+        let new_src = Source::new(Some(&diff[1]), args.pid, args.live)?;
+        if matches!((&old_src, &new_src), (Source::Stdin, Source::Stdin)) {
+            anyhow::bail!("only one side of --diff can read from stdin");
         }
+        let old_block = old_src.read(args.input_format)?;
+        let new_block = new_src.read(args.input_format)?;
+        let old = old_block.to_map(&pattern);
+        let new = new_block.to_map(&pattern);
+        output::write_diff(
+            &mut anstream::stdout().lock(),
+            "",
+            &old,
+            &new,
+            redactor.as_ref(),
+        )?;
+        return Ok(());
+    }
+
+    // anstream strips control characters along with colors when stdout isn't a terminal, which
+    // would corrupt formats that are meant to be exact.
+    let out: Box<dyn io::Write> = if args.format == Format::Text {
+        Box::new(anstream::stdout().lock())
+    } else {
+        Box::new(io::stdout().lock())
     };
+    let mut printer = Printer::new(out, args.format);
+    printer.set_redactor(redactor);
+    printer.set_prefix(args.prefix_source);
+    printer.set_numbered(args.number);
+    if args.split_lists || !args.list_key.is_empty() {
+        printer.set_list_splitter(Some(ListSplitter::new(&args.list_key)?));
+    }
 
-    let pattern = match (args.pattern, args.glob) {
-        (None, _) => Pattern::Empty,
-        (Some(ref pats), true) => {
-            let mut builder = GlobSet::builder();
-            for pat in pats {
-                builder.add(
-                    GlobBuilder::new(pat)
-                        .case_insensitive(!args.case_sensitive)
-                        .build()
-                        .with_context(|| format!("invalid glob '{pat}'"))?,
-                );
+    if let Some(name) = &args.name {
+        let re = Regex::new(name).context("invalid --name regex")?;
+        let procs = find_processes(&re)?;
+        if procs.is_empty() {
+            anyhow::bail!("no processes matching '{name}'");
+        }
+        for proc in procs {
+            let block = match proc.environ() {
+                Ok(block) => block,
+                Err(err) => {
+                    eprintln!(
+                        "Warning: failed to read environment of PID {}: {err}",
+                        proc.pid
+                    );
+                    continue;
+                }
+            };
+            let cmdline = proc.command_line().unwrap_or_default();
+            printer.begin_section(&proc.pid.to_string(), Some(&cmdline))?;
+            print_block(&mut printer, &block, &pattern, order)?;
+        }
+    } else if args.all_pids {
+        printer.set_prefix(true);
+        let own_pid = std::process::id();
+        for pid in envcat::process::pids().context("failed to list processes")? {
+            if pid == own_pid {
+                continue;
+            }
+            let block = match EnvBlock::from_pid(pid) {
+                Ok(block) => block,
+                // permission denied, or the process exited after listing /proc
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::PermissionDenied | io::ErrorKind::NotFound
+                    ) || err.raw_os_error() == Some(3 /* ESRCH */) =>
+                {
+                    continue;
+                }
+                Err(err) => {
+                    eprintln!("Warning: failed to read environment of PID {pid}: {err}");
+                    continue;
+                }
+            };
+            let data = select(&block, &pattern, order);
+            if !data.is_empty() {
+                printer.begin_section(&pid.to_string(), None)?;
+                for (index, entry) in data {
+                    printer.write_entry(index, &entry)?;
+                }
             }
-            Pattern::Glob(builder.build().context("failed to build GlobSet")?)
         }
-        (Some(ref pats), false) => {
-            let mut builder = RegexSetBuilder::new(pats);
-            builder.case_insensitive(!args.case_sensitive);
-            Pattern::Regex(builder.build().context("failed to build RegexSet")?)
+    } else if let Some(regex) = &args.watch {
+        if args.format == Format::Json {
+            anyhow::bail!("--watch never finishes, use --format=json-lines rather than json");
         }
-    };
+        let re = Regex::new(regex).context("invalid --watch regex")?;
+        watch(&mut printer, &re, &pattern, order)?;
+    } else if let Some(pid) = args.tree {
+        if args.format != Format::Text {
+            anyhow::bail!("--tree only supports text output");
+        }
+        print_tree(&mut printer, Process::new(pid), None, 0, &pattern, order)?;
+    } else if let Some(mode) = args.ancestry {
+        if mode == AncestryMode::Diff && args.format != Format::Text {
+            anyhow::bail!("--ancestry=diff only supports text output");
+        }
+        let pid = parse_pid(args.file.as_deref().expect("pid option but no file"))?;
+        let mut chain = Process::new(pid)
+            .ancestry()
+            .with_context(|| format!("failed to find the ancestors of PID {pid}"))?;
+        chain.reverse();
 
-    let buf = match &path {
-        Some(path) => std::fs::read(path).with_context(|| format!("failed to read {path}"))?,
-        None => {
-            let mut buf = Vec::new();
-            io::stdin()
-                .lock()
-                .read_to_end(&mut buf)
-                .context("failed to read stdin")?;
-            buf
+        let mut parent: Option<EnvBlock> = None;
+        for proc in chain {
+            let source = if args.live {
+                Source::LivePid(proc.pid)
+            } else {
+                Source::Pid(proc.pid)
+            };
+            let block = match source.read(args.input_format) {
+                Ok(block) => block,
+                Err(err) => {
+                    // usually permission denied for PID 1, keep going with the rest
+                    eprintln!("Warning: {err:#}");
+                    continue;
+                }
+            };
+            let cmdline = proc.command_line().unwrap_or_default();
+            printer.begin_section(&proc.pid.to_string(), Some(&cmdline))?;
+            match (&parent, mode) {
+                (Some(parent), AncestryMode::Diff) => {
+                    printer.write_diff(&parent.to_map(&pattern), &block.to_map(&pattern))?
+                }
+                _ => print_block(&mut printer, &block, &pattern, order)?,
+            }
+            parent = Some(block);
         }
-    };
+    } else if args.all_threads {
+        let pid = parse_pid(args.file.as_deref().expect("pid option but no file"))?;
+        let proc = Process::new(pid);
+        let tids = proc
+            .threads()
+            .with_context(|| format!("failed to list threads of PID {pid}"))?;
 
-    let mut data: Vec<(&[u8], &[u8])> = buf
-        .split(|b| *b == b'\0')
-        .filter(|chunk| !chunk.is_empty())
-        .map(|chunk| {
-            // [T].split_once() is still unstable :(
-            match chunk.iter().position(|b| *b == b'=') {
-                Some(pos) => {
-                    let (left, right) = chunk.split_at(pos);
-                    (left, &right[1..])
+        // group threads with the same environment, in order of first appearance
+        let mut groups: Vec<(EnvBlock, Vec<u32>)> = Vec::new();
+        for tid in tids {
+            let block = match proc.thread_environ(tid) {
+                Ok(block) => block,
+                // the thread exited after we listed them
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("failed to read /proc/{pid}/task/{tid}/environ"));
                 }
-                None => (chunk, [].as_slice()),
+            };
+            match groups.iter_mut().find(|(b, _)| *b == block) {
+                Some((_, group)) => group.push(tid),
+                None => groups.push((block, vec![tid])),
             }
-        })
-        .collect();
+        }
 
-    if args.sort {
-        data.sort_by_key(|(key, _val)| *key);
-    }
+        let total: usize = groups.iter().map(|(_, tids)| tids.len()).sum();
+        for (block, tids) in &groups {
+            let name: Vec<String> = tids.iter().map(u32::to_string).collect();
+            let detail = if groups.len() == 1 {
+                format!("all {total} threads are identical")
+            } else {
+                format!("{} of {total} threads", tids.len())
+            };
+            printer.begin_section(&name.join(","), Some(&detail))?;
+            print_block(&mut printer, block, &pattern, order)?;
+        }
+    } else if inputs.len() > 1 || args.prefix_source && !args.has_source_option() {
+        if inputs.is_empty() {
+            inputs.push("-".into());
+        }
+        let mut failed = 0;
+        for input in &inputs {
+            let source = Source::new(Some(input), args.pid, args.live)?;
+            let block = match source.read(args.input_format) {
+                Ok(block) => block,
+                Err(err) => {
+                    eprintln!("Warning: {err:#}");
+                    failed += 1;
+                    continue;
+                }
+            };
+            let detail = match source {
+                Source::Pid(pid) | Source::LivePid(pid) => Process::new(pid).command_line().ok(),
+                _ => None,
+            };
+            let name = if input == "-" { "stdin" } else { input };
+            printer.begin_section(name, detail.as_deref())?;
+            print_block(&mut printer, &block, &pattern, order)?;
+        }
+        printer.finish()?;
+        match failed {
+            0 => return Ok(()),
+            1 => anyhow::bail!("failed to read 1 input"),
+            n => anyhow::bail!("failed to read {n} inputs"),
+        }
+    } else {
+        let source = if let Some(path) = &args.core {
+            Source::Core(path.clone())
+        } else if let Some(unit) = &args.systemd_unit {
+            Source::SystemdUnit(unit.clone())
+        } else if let Some(path) = &args.oci {
+            Source::Oci(path.clone())
+        } else if let Some(tid) = args.tid {
+            let pid = parse_pid(args.file.as_deref().expect("pid option but no file"))?;
+            Source::Thread(pid, tid)
+        } else {
+            Source::new(args.file.as_deref(), args.pid, args.live)?
+        };
+        // without sorting, NUL-separated input can be printed as it's read rather than buffering
+        // all of it first
+        let streamable = !args.lint
+            && args.input_format == InputFormat::Nul
+            && order.sort == SortKey::None
+            && !order.reverse;
+        if streamable && let Some((reader, name)) = source.stream()? {
+            print_stream(&mut printer, reader, &name, &pattern)?;
+            return printer.finish().map_err(Into::into);
+        }
 
-    let mut out = anstream::stdout().lock();
-    for (key, val) in data.into_iter() {
-        if pattern.is_match(key) {
-            write_pair(&mut out, key, val)?;
+        let block = source.read(args.input_format)?;
+        if args.lint {
+            let findings = lint::lint(&block, &pattern);
+            // the printer already holds the stdout lock, but it's reentrant
+            let mut out = io::stdout().lock();
+            for finding in &findings {
+                writeln!(out, "{finding}")?;
+            }
+            out.flush()?;
+            match findings.len() {
+                0 => return Ok(()),
+                1 => anyhow::bail!("found 1 problem"),
+                n => anyhow::bail!("found {n} problems"),
+            }
         }
+        print_block(&mut printer, &block, &pattern, order)?;
     }
+    printer.finish()?;
 
     Ok(())
 }
@@ -0,0 +1,287 @@
+//! Read the environment from container image and runtime configs, without a container runtime.
+//!
+//! Supported inputs are:
+//!  * an OCI image config or `docker inspect` output, using `config.Env`
+//!  * an OCI runtime bundle's `config.json`, using `process.env`
+//!  * an unpacked OCI image layout directory (with `index.json`), or the output of `docker save`
+//!    (with `manifest.json`), which are followed to the image config
+//!  * a runtime bundle directory containing `config.json`
+//!
+//! Multi-platform images use the first manifest.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Component, Path};
+
+use serde_json::Value;
+
+use crate::EnvBlock;
+
+/// Places in a config where the env array can be
+const ENV_POINTERS: &[&str] = &[
+    "/config/Env",
+    "/Config/Env",
+    "/0/Config/Env",
+    "/process/env",
+];
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn read_json(path: &Path) -> io::Result<Value> {
+    let buf = fs::read(path)?;
+    serde_json::from_slice(&buf)
+        .map_err(|err| invalid(format!("{}: invalid JSON: {err}", path.display())))
+}
+
+/// Read the environment from a config file or image directory at `path`
+pub fn read_path(path: impl AsRef<Path>) -> io::Result<EnvBlock> {
+    let path = path.as_ref();
+    let config = if path.is_dir() {
+        read_dir_config(path)?
+    } else {
+        read_json(path)?
+    };
+    env_from_config(&config).ok_or_else(|| {
+        invalid(format!(
+            "{}: no environment found in config",
+            path.display()
+        ))
+    })?
+}
+
+/// Get the environment from a parsed image or runtime config. Returns `None` if there's no env
+/// array in any of the expected places.
+pub fn env_from_config(config: &Value) -> Option<io::Result<EnvBlock>> {
+    let env = ENV_POINTERS.iter().find_map(|ptr| config.pointer(ptr))?;
+    let Some(env) = env.as_array() else {
+        return Some(Err(invalid("env isn't an array")));
+    };
+    let mut buf = Vec::new();
+    for item in env {
+        let Some(item) = item.as_str() else {
+            return Some(Err(invalid("env array item isn't a string")));
+        };
+        buf.extend_from_slice(item.as_bytes());
+        buf.push(b'\0');
+    }
+    Some(Ok(EnvBlock::new(buf)))
+}
+
+/// Find and read the config in a runtime bundle or image directory
+fn read_dir_config(dir: &Path) -> io::Result<Value> {
+    let runtime = dir.join("config.json");
+    if runtime.exists() {
+        return read_json(&runtime);
+    }
+
+    let index = dir.join("index.json");
+    if index.exists() {
+        let mut manifest = read_json(&index)?;
+        // follow nested indexes until we get to an image manifest with a config, but the image
+        // may not be trusted so watch out for cycles
+        let mut seen = HashSet::new();
+        loop {
+            if let Some(digest) = manifest.pointer("/config/digest").and_then(Value::as_str) {
+                return read_json(&blob_path(dir, digest)?);
+            }
+            let digest = manifest
+                .pointer("/manifests/0/digest")
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid("image index has no manifests"))?;
+            if !seen.insert(digest.to_owned()) {
+                return Err(invalid(format!("loop in image indexes at {digest}")));
+            }
+            manifest = read_json(&blob_path(dir, digest)?)?;
+        }
+    }
+
+    let docker_manifest = dir.join("manifest.json");
+    if docker_manifest.exists() {
+        let manifest = read_json(&docker_manifest)?;
+        let config = manifest
+            .pointer("/0/Config")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("manifest.json has no Config"))?;
+        // like digests, don't let the path point outside the image
+        let is_inside = Path::new(config)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !is_inside {
+            return Err(invalid(format!("invalid config path '{config}'")));
+        }
+        return read_json(&dir.join(config));
+    }
+
+    Err(invalid(
+        "no config.json, index.json, or manifest.json in directory",
+    ))
+}
+
+/// Get the path of a blob in an OCI image layout from its `<algorithm>:<hex>` digest
+fn blob_path(dir: &Path, digest: &str) -> io::Result<std::path::PathBuf> {
+    let (algorithm, hex) = digest
+        .split_once(':')
+        .ok_or_else(|| invalid(format!("invalid digest '{digest}'")))?;
+    // digests come from the file, don't let them point outside the blobs directory
+    if algorithm.contains(['/', '.']) || hex.contains(['/', '.']) {
+        return Err(invalid(format!("invalid digest '{digest}'")));
+    }
+    Ok(dir.join("blobs").join(algorithm).join(hex))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A temporary directory with some files in it, removed when dropped
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("envcat-oci-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            for (path, contents) in files {
+                let path = dir.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+            Self(dir)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const CONFIG: &str = r#"{"architecture": "amd64", "config": {"Env": ["A=1", "B=2"]}}"#;
+
+    fn entries(block: EnvBlock) -> Vec<String> {
+        block.entries().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn image_config() {
+        let fixture = Fixture::new("image", &[("config.json", CONFIG)]);
+        let path = fixture.0.join("config.json");
+        assert_eq!(entries(read_path(&path).unwrap()), ["A=1", "B=2"]);
+    }
+
+    #[test]
+    fn docker_inspect() {
+        let inspect = r#"[{"Id": "sha256:a", "Config": {"Env": ["A=1"]}}]"#;
+        let fixture = Fixture::new("inspect", &[("inspect.json", inspect)]);
+        let path = fixture.0.join("inspect.json");
+        assert_eq!(entries(read_path(&path).unwrap()), ["A=1"]);
+    }
+
+    #[test]
+    fn runtime_config() {
+        let config = r#"{"ociVersion": "1.0.2", "process": {"env": ["PATH=/bin", "A=1"]}}"#;
+        let fixture = Fixture::new("runtime", &[("config.json", config)]);
+        // both the file itself and the bundle directory
+        assert_eq!(
+            entries(read_path(fixture.0.join("config.json")).unwrap()),
+            ["PATH=/bin", "A=1"]
+        );
+        assert_eq!(
+            entries(read_path(&fixture.0).unwrap()),
+            ["PATH=/bin", "A=1"]
+        );
+    }
+
+    #[test]
+    fn oci_layout() {
+        let fixture = Fixture::new(
+            "layout",
+            &[
+                ("oci-layout", r#"{"imageLayoutVersion": "1.0.0"}"#),
+                (
+                    "index.json",
+                    r#"{"manifests": [{"digest": "sha256:index"}]}"#,
+                ),
+                // a multi-platform image, where the first manifest is used
+                (
+                    "blobs/sha256/index",
+                    r#"{"manifests": [{"digest": "sha256:amd64"}, {"digest": "sha256:arm64"}]}"#,
+                ),
+                (
+                    "blobs/sha256/amd64",
+                    r#"{"config": {"digest": "sha256:config"}}"#,
+                ),
+                (
+                    "blobs/sha256/arm64",
+                    r#"{"config": {"digest": "sha256:other"}}"#,
+                ),
+                ("blobs/sha256/config", CONFIG),
+            ],
+        );
+        assert_eq!(entries(read_path(&fixture.0).unwrap()), ["A=1", "B=2"]);
+    }
+
+    #[test]
+    fn bad_digest() {
+        let fixture = Fixture::new(
+            "digest",
+            &[(
+                "index.json",
+                r#"{"manifests": [{"digest": "sha256:../../x"}]}"#,
+            )],
+        );
+        let err = read_path(&fixture.0).unwrap_err();
+        assert_eq!(err.to_string(), "invalid digest 'sha256:../../x'");
+    }
+
+    #[test]
+    fn docker_save() {
+        let manifest = r#"[{"Config": "abc.json", "RepoTags": ["x:latest"], "Layers": []}]"#;
+        let fixture = Fixture::new("save", &[("manifest.json", manifest), ("abc.json", CONFIG)]);
+        assert_eq!(entries(read_path(&fixture.0).unwrap()), ["A=1", "B=2"]);
+    }
+
+    #[test]
+    fn docker_save_outside_path() {
+        for config in ["/etc/passwd", "../abc.json", "blobs/../../abc.json"] {
+            let manifest = format!(r#"[{{"Config": "{config}"}}]"#);
+            let fixture = Fixture::new("outside", &[("manifest.json", &manifest)]);
+            let err = read_path(&fixture.0).unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid config path '{config}'"));
+        }
+    }
+
+    #[test]
+    fn no_env() {
+        let fixture = Fixture::new("noenv", &[("config.json", r#"{"config": {}}"#)]);
+        let path = fixture.0.join("config.json");
+        let err = read_path(&path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("{}: no environment found in config", path.display())
+        );
+    }
+
+    #[test]
+    fn index_loop() {
+        let index = r#"{"manifests": [{"digest": "sha256:a"}]}"#;
+        let fixture = Fixture::new(
+            "loop",
+            &[
+                ("index.json", index),
+                (
+                    "blobs/sha256/a",
+                    r#"{"manifests": [{"digest": "sha256:b"}]}"#,
+                ),
+                ("blobs/sha256/b", index),
+            ],
+        );
+        let err = read_path(&fixture.0).unwrap_err();
+        assert_eq!(err.to_string(), "loop in image indexes at sha256:a");
+    }
+}
@@ -0,0 +1,551 @@
+//! Output formatting for the envcat CLI
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anstyle::{AnsiColor, Style};
+use clap::ValueEnum;
+use envcat::EnvEntry;
+
+use crate::lists::{Element, ListSplitter};
+use crate::redact::Redactor;
+use crate::shell;
+
+const STYLE_KEY: Style = color_style(AnsiColor::Green);
+const STYLE_EQU: Style = color_style(AnsiColor::Blue);
+const STYLE_VAL: Style = Style::new();
+const STYLE_ADD: Style = color_style(AnsiColor::Green);
+const STYLE_DEL: Style = color_style(AnsiColor::Red);
+const STYLE_CHG: Style = color_style(AnsiColor::Yellow);
+const STYLE_HDR: Style = Style::new().bold();
+const STYLE_SRC: Style = color_style(AnsiColor::Magenta);
+const STYLE_NUM: Style = color_style(AnsiColor::Cyan);
+
+const fn color_style(color: AnsiColor) -> Style {
+    Style::new().fg_color(Some(anstyle::Color::Ansi(color)))
+}
+
+/// pretty-print a key/value pair to `out`
+pub fn write_pair<W: Write>(out: &mut W, key: &[u8], val: &[u8]) -> io::Result<()> {
+    // [u8] isn't Display so do it ourselves
+    STYLE_KEY.write_to(out)?;
+    out.write_all(key)?;
+    STYLE_KEY.write_reset_to(out)?;
+    write!(out, "{}={}", STYLE_EQU.render(), STYLE_EQU.render_reset())?;
+    if !val.is_empty() {
+        STYLE_VAL.write_to(out)?;
+        out.write_all(val)?;
+        STYLE_VAL.write_reset_to(out)?;
+    }
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+/// pretty-print a list variable to `out` with each element on its own line after `indent`, and
+/// problems marked next to the element
+fn write_list<W: Write>(
+    out: &mut W,
+    indent: &str,
+    key: &[u8],
+    elements: &[Element<'_>],
+) -> io::Result<()> {
+    write_pair(out, key, b"")?;
+    for element in elements {
+        write!(out, "{indent}    ")?;
+        STYLE_VAL.write_to(out)?;
+        out.write_all(element.value)?;
+        STYLE_VAL.write_reset_to(out)?;
+        if let Some(problem) = element.problem {
+            if !element.value.is_empty() {
+                out.write_all(b" ")?;
+            }
+            write!(
+                out,
+                "{}({problem}){}",
+                STYLE_CHG.render(),
+                STYLE_CHG.render_reset()
+            )?;
+        }
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// pretty-print a key/value pair to `out` with a colored diff marker in front of it
+fn write_diff_pair<W: Write>(
+    out: &mut W,
+    indent: &str,
+    marker: &str,
+    style: Style,
+    key: &[u8],
+    val: &[u8],
+) -> io::Result<()> {
+    write!(
+        out,
+        "{indent}{}{marker}{}",
+        style.render(),
+        style.render_reset()
+    )?;
+    write_pair(out, key, val)
+}
+
+/// Print the variables added, removed, or changed going from `old` to `new`
+///
+/// Changes are detected using the real values, even if they're displayed redacted. Each line
+/// starts with `indent`.
+pub fn write_diff<W: Write>(
+    out: &mut W,
+    indent: &str,
+    old: &BTreeMap<&[u8], &[u8]>,
+    new: &BTreeMap<&[u8], &[u8]>,
+    redactor: Option<&Redactor>,
+) -> io::Result<()> {
+    let show = |key, val| match redactor {
+        Some(redactor) => redactor.redact(key, val),
+        None => Cow::Borrowed(val),
+    };
+    let keys: BTreeSet<&[u8]> = old.keys().chain(new.keys()).copied().collect();
+    for key in keys {
+        match (old.get(key), new.get(key)) {
+            (Some(old_val), None) => {
+                write_diff_pair(out, indent, "-", STYLE_DEL, key, &show(key, old_val))?
+            }
+            (None, Some(new_val)) => {
+                write_diff_pair(out, indent, "+", STYLE_ADD, key, &show(key, new_val))?
+            }
+            (Some(old_val), Some(new_val)) if old_val != new_val => {
+                write_diff_pair(out, indent, "~", STYLE_CHG, key, &show(key, old_val))?;
+                write_diff_pair(out, indent, "~", STYLE_CHG, key, &show(key, new_val))?;
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// How to print the selected entries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Colored `<name>=<value>` lines
+    #[default]
+    Text,
+    /// `<name>=<value>` entries each followed by a NUL byte, like `/proc/<pid>/environ`
+    Nul,
+    /// A single JSON object mapping names to values
+    Json,
+    /// One `{"key": <name>, "value": <value>}` JSON object per line
+    JsonLines,
+    /// POSIX sh `export NAME='value'` statements
+    Sh,
+    /// bash `export` statements, using `$'...'` quoting for unprintable values
+    Bash,
+    /// fish `set -gx NAME 'value'` statements
+    Fish,
+    /// zsh `export` statements, using `$'...'` quoting for unprintable values
+    Zsh,
+    /// PowerShell `$env:NAME = 'value'` statements
+    Powershell,
+}
+
+impl Format {
+    /// Whether only the first of several entries with the same name is printed. A JSON object
+    /// can't have duplicate keys, and evaluating shell statements keeps the last value, but
+    /// getenv() returns the first.
+    pub fn unique(self) -> bool {
+        !matches!(self, Self::Text | Self::Nul | Self::JsonLines)
+    }
+}
+
+/// Writes entries to an output stream in the chosen [`Format`].
+///
+/// Entries can optionally be grouped into sections when printing more than one environment,
+/// see [`begin_section`](Self::begin_section). Call [`finish`](Self::finish) after the last
+/// entry, some formats need a footer.
+pub struct Printer<W: Write> {
+    out: W,
+    format: Format,
+    /// number of entries in the current section
+    count: usize,
+    /// number of sections started so far
+    sections: usize,
+    /// name of the current section
+    section: Option<String>,
+    /// prefix text lines with the section name rather than printing headers
+    prefix: bool,
+    /// nesting level of text output, see `set_indent`
+    indent: usize,
+    /// print the entries' original indexes in text format
+    numbered: bool,
+    redactor: Option<Redactor>,
+    lists: Option<ListSplitter>,
+    /// names already written in the current section, for [`Format::unique`]
+    seen_keys: HashSet<Vec<u8>>,
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(out: W, format: Format) -> Self {
+        Self {
+            out,
+            format,
+            count: 0,
+            sections: 0,
+            section: None,
+            prefix: false,
+            indent: 0,
+            numbered: false,
+            redactor: None,
+            lists: None,
+            seen_keys: HashSet::new(),
+        }
+    }
+
+    /// Hide secret values in all formats
+    pub fn set_redactor(&mut self, redactor: Option<Redactor>) {
+        self.redactor = redactor;
+    }
+
+    /// In text format, print the index passed to [`write_entry`](Self::write_entry) in front of
+    /// each entry
+    pub fn set_numbered(&mut self, numbered: bool) {
+        self.numbered = numbered;
+    }
+
+    /// In text format, print list variables like PATH with one element per line
+    pub fn set_list_splitter(&mut self, lists: Option<ListSplitter>) {
+        self.lists = lists;
+    }
+
+    /// In text format, prefix each line with the section name like `grep -H` rather than
+    /// printing a header for each section. Other formats are unaffected.
+    pub fn set_prefix(&mut self, prefix: bool) {
+        self.prefix = prefix;
+    }
+
+    /// In text format, indent headers and entries by `level` steps, e.g. to show a tree of
+    /// sections. Other formats are unaffected.
+    pub fn set_indent(&mut self, level: usize) {
+        self.indent = level;
+    }
+
+    fn indent(&self) -> String {
+        match self.format {
+            Format::Text => "  ".repeat(self.indent),
+            _ => String::new(),
+        }
+    }
+
+    /// Start a new group of entries, e.g. the environment of one process out of many.
+    ///
+    /// Text and shell formats print a header with `name` and the optional `detail`. JSON nests
+    /// each section in the top-level object under `name`, and JSON lines adds a `source` field
+    /// with `name` to each entry. NUL output has nowhere to put a header, so sections are just
+    /// concatenated.
+    pub fn begin_section(&mut self, name: &str, detail: Option<&str>) -> io::Result<()> {
+        let indent = self.indent();
+        let out = &mut self.out;
+        let title = match detail {
+            Some(detail) => format!("{name}: {detail}"),
+            None => name.to_owned(),
+        };
+        // escape control characters so that e.g. a newline in a command line can't end the
+        // header (or a shell comment) early
+        let title: String = title
+            .chars()
+            .map(|c| {
+                if c.is_control() {
+                    c.escape_default().to_string()
+                } else {
+                    c.to_string()
+                }
+            })
+            .collect();
+        match self.format {
+            Format::Text if self.prefix => (),
+            Format::Text => {
+                if self.sections > 0 {
+                    out.write_all(b"\n")?;
+                }
+                writeln!(
+                    out,
+                    "{indent}{}==> {title} <=={}",
+                    STYLE_HDR.render(),
+                    STYLE_HDR.render_reset()
+                )?;
+            }
+            Format::Json => {
+                if self.sections == 0 {
+                    out.write_all(b"{\n  ")?;
+                } else {
+                    self.close_json_section()?;
+                    self.out.write_all(b",\n  ")?;
+                }
+                write_json_str(&mut self.out, name.as_bytes())?;
+                self.out.write_all(b": {")?;
+            }
+            Format::Nul | Format::JsonLines => (),
+            Format::Sh | Format::Bash | Format::Fish | Format::Zsh | Format::Powershell => {
+                if self.sections > 0 {
+                    out.write_all(b"\n")?;
+                }
+                writeln!(out, "# {title}")?;
+            }
+        }
+        self.count = 0;
+        self.seen_keys.clear();
+        self.sections += 1;
+        self.section = Some(name.to_owned());
+        Ok(())
+    }
+
+    fn close_json_section(&mut self) -> io::Result<()> {
+        self.out
+            .write_all(if self.count == 0 { b"}" } else { b"\n  }" })
+    }
+
+    /// Print the differences between `old` and `new`, see [`write_diff`]. Diffs are only
+    /// supported in text format.
+    pub fn write_diff(
+        &mut self,
+        old: &BTreeMap<&[u8], &[u8]>,
+        new: &BTreeMap<&[u8], &[u8]>,
+    ) -> io::Result<()> {
+        assert_eq!(
+            self.format,
+            Format::Text,
+            "diffs are only supported in text format"
+        );
+        let indent = self.indent();
+        write_diff(&mut self.out, &indent, old, new, self.redactor.as_ref())
+    }
+
+    /// Print an entry. `index` is its position in the original environment, which is only shown
+    /// if enabled with [`set_numbered`](Self::set_numbered).
+    pub fn write_entry(&mut self, index: usize, entry: &EnvEntry<'_>) -> io::Result<()> {
+        if self.format.unique() && !self.seen_keys.insert(entry.key.to_vec()) {
+            return Ok(());
+        }
+        let redacted;
+        let entry = match &self.redactor {
+            Some(redactor) => {
+                redacted = redactor.redact(entry.key, entry.value);
+                &EnvEntry {
+                    key: entry.key,
+                    value: &redacted,
+                }
+            }
+            None => entry,
+        };
+
+        let indent = self.indent();
+        let out = &mut self.out;
+        match self.format {
+            Format::Text => {
+                out.write_all(indent.as_bytes())?;
+                if self.prefix
+                    && let Some(section) = &self.section
+                {
+                    write!(
+                        out,
+                        "{}{section}{}{}:{}",
+                        STYLE_SRC.render(),
+                        STYLE_SRC.render_reset(),
+                        STYLE_EQU.render(),
+                        STYLE_EQU.render_reset()
+                    )?;
+                }
+                if self.numbered {
+                    write!(
+                        out,
+                        "{}{index:>4}{} ",
+                        STYLE_NUM.render(),
+                        STYLE_NUM.render_reset()
+                    )?;
+                }
+                match self
+                    .lists
+                    .as_ref()
+                    .and_then(|lists| lists.split(entry.key, entry.value))
+                {
+                    Some(elements) => write_list(out, &indent, entry.key, &elements)?,
+                    None => write_pair(out, entry.key, entry.value)?,
+                }
+            }
+            Format::Json => {
+                let indent = if self.sections > 0 { "    " } else { "  " };
+                if self.count == 0 && self.sections == 0 {
+                    out.write_all(b"{")?;
+                } else if self.count > 0 {
+                    out.write_all(b",")?;
+                }
+                write!(out, "\n{indent}")?;
+                if is_utf8(entry.key) && is_utf8(entry.value) {
+                    write_json_str(out, entry.key)?;
+                    out.write_all(b": ")?;
+                    write_json_str(out, entry.value)?;
+                } else if is_utf8(entry.key) {
+                    write_json_str(out, entry.key)?;
+                    out.write_all(b": ")?;
+                    write_json_entry(out, entry, None)?;
+                } else {
+                    // a lossy key could collide with other keys, the full object has the exact
+                    // bytes
+                    write!(out, "\"{}\"", base64(entry.key))?;
+                    out.write_all(b": ")?;
+                    write_json_entry(out, entry, None)?;
+                }
+            }
+            Format::Nul => {
+                out.write_all(entry.key)?;
+                out.write_all(b"=")?;
+                out.write_all(entry.value)?;
+                out.write_all(b"\0")?;
+            }
+            Format::JsonLines => {
+                write_json_entry(out, entry, self.section.as_deref())?;
+                out.write_all(b"\n")?;
+            }
+            Format::Sh | Format::Bash | Format::Fish | Format::Zsh
+                if !shell::is_identifier(entry.key) =>
+            {
+                eprintln!(
+                    "Warning: skipping variable with invalid name '{}'",
+                    String::from_utf8_lossy(entry.key).escape_debug()
+                );
+                return Ok(());
+            }
+            Format::Sh => shell::write_sh(out, entry.key, entry.value)?,
+            Format::Bash | Format::Zsh => shell::write_bash(out, entry.key, entry.value)?,
+            Format::Fish => shell::write_fish(out, entry.key, entry.value)?,
+            Format::Powershell => {
+                if !shell::write_powershell(out, entry.key, entry.value)? {
+                    eprintln!(
+                        "Warning: variable '{}' isn't valid UTF-8, PowerShell output is lossy",
+                        String::from_utf8_lossy(entry.key).escape_debug()
+                    );
+                }
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Flush the output, e.g. when more entries won't come for a while
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.format == Format::Json {
+            if self.sections > 0 {
+                self.close_json_section()?;
+                self.out.write_all(b"\n}\n")?;
+            } else {
+                self.out
+                    .write_all(if self.count == 0 { b"{}\n" } else { b"\n}\n" })?;
+            }
+        }
+        self.out.flush()
+    }
+}
+
+/// Format `time` as an RFC 3339 UTC timestamp with milliseconds
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // convert days since 1970-01-01 to a date in the proleptic Gregorian calendar, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+fn is_utf8(bytes: &[u8]) -> bool {
+    std::str::from_utf8(bytes).is_ok()
+}
+
+/// Write an entry as a `{"key": ..., "value": ...}` JSON object.
+///
+/// JSON strings can't hold arbitrary bytes, so when the key or value isn't valid UTF-8, the
+/// string field gets a lossy conversion (invalid sequences replaced by U+FFFD) and an extra
+/// `key_base64` or `value_base64` field holds the exact bytes.
+///
+/// If given, `source` is added as the first field.
+fn write_json_entry<W: Write>(
+    out: &mut W,
+    entry: &EnvEntry<'_>,
+    source: Option<&str>,
+) -> io::Result<()> {
+    out.write_all(b"{")?;
+    if let Some(source) = source {
+        out.write_all(b"\"source\": ")?;
+        write_json_str(out, source.as_bytes())?;
+        out.write_all(b", ")?;
+    }
+    out.write_all(b"\"key\": ")?;
+    write_json_str(out, entry.key)?;
+    if !is_utf8(entry.key) {
+        write!(out, ", \"key_base64\": \"{}\"", base64(entry.key))?;
+    }
+    out.write_all(b", \"value\": ")?;
+    write_json_str(out, entry.value)?;
+    if !is_utf8(entry.value) {
+        write!(out, ", \"value_base64\": \"{}\"", base64(entry.value))?;
+    }
+    out.write_all(b"}")
+}
+
+/// Write a quoted and escaped JSON string, replacing invalid UTF-8 with U+FFFD
+fn write_json_str<W: Write>(out: &mut W, bytes: &[u8]) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in String::from_utf8_lossy(bytes).chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{c}")?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+/// Standard padded base64 encoding
+fn base64(bytes: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (u32::from(*b) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(TABLE[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
@@ -0,0 +1,358 @@
+use std::fmt;
+
+use globset::{GlobBuilder, GlobSet};
+use regex::bytes::{RegexSet, RegexSetBuilder};
+
+use crate::EnvEntry;
+
+/// Something that decides whether an [`EnvEntry`] should be selected.
+///
+/// This is implemented for [`Pattern`], as well as any `Fn(&EnvEntry) -> bool` closure.
+pub trait Filter {
+    fn matches(&self, entry: &EnvEntry<'_>) -> bool;
+}
+
+impl<F: Fn(&EnvEntry<'_>) -> bool> Filter for F {
+    fn matches(&self, entry: &EnvEntry<'_>) -> bool {
+        self(entry)
+    }
+}
+
+/// `GlobSet` only matches on `AsRef<Path>` types, extend it to accept arbitrary bytes as input too,
+/// since we're using it to match key names rather than file paths.
+trait GlobExt {
+    fn is_match_bytes(&self, needle: &[u8]) -> bool;
+}
+
+impl GlobExt for GlobSet {
+    fn is_match_bytes(&self, needle: &[u8]) -> bool {
+        // On unix, Path and OsStr are interchangeable with `&[u8]`
+        #[cfg(unix)]
+        {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+            self.is_match(OsStr::from_bytes(needle))
+        }
+
+        // Otherwise (e.g. on windows) we can't directly interchange bytes and OsStr, so loop through
+        // UTF-8 to get a string that implements AsRef<Path>
+        #[cfg(not(unix))]
+        {
+            let s = String::from_utf8_lossy(needle);
+            self.is_match(&*s)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Empty,
+    Glob(GlobSet),
+    Regex(RegexSet),
+}
+
+impl Matcher {
+    fn new(patterns: &[String], glob: bool, case_sensitive: bool) -> Result<Self, PatternError> {
+        if patterns.is_empty() {
+            Ok(Self::Empty)
+        } else if glob {
+            let mut builder = GlobSet::builder();
+            for pat in patterns {
+                builder.add(
+                    GlobBuilder::new(pat)
+                        .case_insensitive(!case_sensitive)
+                        .build()
+                        .map_err(|err| PatternError::Glob(pat.clone(), err))?,
+                );
+            }
+            Ok(Self::Glob(builder.build().map_err(PatternError::GlobSet)?))
+        } else {
+            let mut builder = RegexSetBuilder::new(patterns);
+            builder.case_insensitive(!case_sensitive);
+            Ok(Self::Regex(builder.build().map_err(PatternError::Regex)?))
+        }
+    }
+
+    fn is_match(&self, name: &[u8]) -> bool {
+        match self {
+            Self::Empty => true,
+            Self::Glob(globs) => globs.is_match_bytes(name),
+            Self::Regex(regexes) => regexes.is_match(name),
+        }
+    }
+}
+
+/// A compiled set of key name and value patterns, built by [`PatternBuilder`].
+///
+/// A key matches if it matches any of the key patterns and none of the exclude patterns, and
+/// values match if they match any of the value patterns. An entry matches if both its key and
+/// value match. An empty pattern set matches everything.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    keys: Matcher,
+    exclude: Option<Matcher>,
+    values: Matcher,
+}
+
+impl Pattern {
+    /// A pattern which matches everything
+    pub fn empty() -> Self {
+        Self {
+            keys: Matcher::Empty,
+            exclude: None,
+            values: Matcher::Empty,
+        }
+    }
+
+    /// Check whether a key name matches this pattern
+    pub fn is_match(&self, key: &[u8]) -> bool {
+        self.keys.is_match(key) && !self.exclude.as_ref().is_some_and(|ex| ex.is_match(key))
+    }
+
+    /// Check whether a value matches this pattern
+    pub fn is_value_match(&self, value: &[u8]) -> bool {
+        self.values.is_match(value)
+    }
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Filter for Pattern {
+    fn matches(&self, entry: &EnvEntry<'_>) -> bool {
+        self.is_match(entry.key) && self.is_value_match(entry.value)
+    }
+}
+
+/// Builder for a [`Pattern`].
+///
+/// By default, patterns are case-insensitive regexes.
+#[derive(Debug, Clone, Default)]
+pub struct PatternBuilder {
+    patterns: Vec<String>,
+    exclude_patterns: Vec<String>,
+    value_patterns: Vec<String>,
+    glob: bool,
+    case_sensitive: bool,
+}
+
+impl PatternBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a pattern to the set
+    pub fn add(&mut self, pattern: impl Into<String>) -> &mut Self {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    /// Add several patterns to the set
+    pub fn extend<I, S>(&mut self, patterns: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.patterns.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Add a pattern for keys which should never match, even if they match an include pattern
+    pub fn exclude(&mut self, pattern: impl Into<String>) -> &mut Self {
+        self.exclude_patterns.push(pattern.into());
+        self
+    }
+
+    /// Add several exclude patterns to the set
+    pub fn extend_exclude<I, S>(&mut self, patterns: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.exclude_patterns
+            .extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Add a pattern for values to the set
+    pub fn add_value(&mut self, pattern: impl Into<String>) -> &mut Self {
+        self.value_patterns.push(pattern.into());
+        self
+    }
+
+    /// Add several value patterns to the set
+    pub fn extend_values<I, S>(&mut self, patterns: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.value_patterns
+            .extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Treat patterns as globs rather than regexes
+    pub fn glob(&mut self, yes: bool) -> &mut Self {
+        self.glob = yes;
+        self
+    }
+
+    /// Make patterns case-sensitive
+    pub fn case_sensitive(&mut self, yes: bool) -> &mut Self {
+        self.case_sensitive = yes;
+        self
+    }
+
+    /// Compile the patterns
+    pub fn build(&self) -> Result<Pattern, PatternError> {
+        Ok(Pattern {
+            keys: Matcher::new(&self.patterns, self.glob, self.case_sensitive)?,
+            exclude: if self.exclude_patterns.is_empty() {
+                None
+            } else {
+                Some(Matcher::new(
+                    &self.exclude_patterns,
+                    self.glob,
+                    self.case_sensitive,
+                )?)
+            },
+            values: Matcher::new(&self.value_patterns, self.glob, self.case_sensitive)?,
+        })
+    }
+}
+
+/// An error compiling a [`Pattern`]
+#[derive(Debug)]
+pub enum PatternError {
+    /// An invalid glob
+    Glob(String, globset::Error),
+    /// Failed to build the `GlobSet`
+    GlobSet(globset::Error),
+    /// Failed to build the `RegexSet`, e.g. due to an invalid regex
+    Regex(regex::Error),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Glob(pat, _) => write!(f, "invalid glob '{pat}'"),
+            Self::GlobSet(_) => f.write_str("failed to build GlobSet"),
+            Self::Regex(_) => f.write_str("failed to build RegexSet"),
+        }
+    }
+}
+
+impl std::error::Error for PatternError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Glob(_, err) | Self::GlobSet(err) => Some(err),
+            Self::Regex(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry<'a>(key: &'a str, value: &'a str) -> EnvEntry<'a> {
+        EnvEntry {
+            key: key.as_bytes(),
+            value: value.as_bytes(),
+        }
+    }
+
+    #[test]
+    fn empty() {
+        for pattern in [Pattern::empty(), PatternBuilder::new().build().unwrap()] {
+            assert!(pattern.matches(&entry("ANY", "thing")));
+            assert!(pattern.matches(&entry("", "")));
+            assert!(pattern.is_match(b"\xff"));
+            assert!(pattern.is_value_match(b"\xff"));
+        }
+    }
+
+    #[test]
+    fn regex_keys() {
+        let pattern = PatternBuilder::new()
+            .extend(["^path$", "^LC_"])
+            .build()
+            .unwrap();
+        assert!(pattern.is_match(b"PATH"));
+        assert!(pattern.is_match(b"LC_ALL"));
+        assert!(!pattern.is_match(b"MANPATH"));
+
+        let pattern = PatternBuilder::new()
+            .add("^path$")
+            .case_sensitive(true)
+            .build()
+            .unwrap();
+        assert!(!pattern.is_match(b"PATH"));
+        assert!(pattern.is_match(b"path"));
+    }
+
+    #[test]
+    fn exclude_overrides_include() {
+        let pattern = PatternBuilder::new()
+            .add("^LC_")
+            .exclude("^LC_ALL$")
+            .build()
+            .unwrap();
+        assert!(pattern.is_match(b"LC_CTYPE"));
+        assert!(!pattern.is_match(b"LC_ALL"));
+        assert!(!pattern.is_match(b"lc_all"));
+        assert!(!pattern.is_match(b"HOME"));
+
+        // with no include patterns, everything but the excluded names matches
+        let pattern = PatternBuilder::new()
+            .extend_exclude(["*_TOKEN", "AWS_*"])
+            .glob(true)
+            .build()
+            .unwrap();
+        assert!(pattern.is_match(b"HOME"));
+        assert!(!pattern.is_match(b"GITHUB_TOKEN"));
+        assert!(!pattern.is_match(b"aws_region"));
+    }
+
+    #[test]
+    fn values() {
+        let pattern = PatternBuilder::new()
+            .add_value("/usr/.*/bin")
+            .build()
+            .unwrap();
+        assert!(pattern.matches(&entry("PATH", "/bin:/usr/local/bin")));
+        assert!(pattern.matches(&entry("PATH", "/USR/LOCAL/BIN")));
+        assert!(!pattern.matches(&entry("PATH", "/bin")));
+
+        // both the key and the value have to match
+        let pattern = PatternBuilder::new()
+            .add("*PATH")
+            .extend_values(["/usr/*", "*/local/*"])
+            .glob(true)
+            .case_sensitive(true)
+            .build()
+            .unwrap();
+        assert!(pattern.matches(&entry("PATH", "/usr/bin")));
+        assert!(pattern.matches(&entry("MANPATH", "/opt/local/man")));
+        assert!(!pattern.matches(&entry("PATH", "/USR/bin")));
+        assert!(!pattern.matches(&entry("HOME", "/usr/home")));
+        assert!(!pattern.matches(&entry("path", "/usr/bin")));
+    }
+
+    #[test]
+    fn errors() {
+        let err = PatternBuilder::new().add("(").build().unwrap_err();
+        assert!(matches!(err, PatternError::Regex(_)));
+        let err = PatternBuilder::new()
+            .exclude("[")
+            .glob(true)
+            .build()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invalid glob '['");
+        let err = PatternBuilder::new().add_value("a{").glob(true).build();
+        assert!(err.is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::EnvBlock;
+
+/// List the PIDs of all running processes, in ascending order.
+pub fn pids() -> io::Result<Vec<u32>> {
+    let mut pids: Vec<u32> = fs::read_dir("/proc")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    pids.sort_unstable();
+    Ok(pids)
+}
+
+/// A running process, inspected through `/proc/<pid>`.
+///
+/// This is only a handle, every method reads `/proc` again and can fail if the process has
+/// exited or belongs to another user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Process {
+    pub pid: u32,
+}
+
+impl Process {
+    pub fn new(pid: u32) -> Self {
+        Self { pid }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        PathBuf::from(format!("/proc/{}/{name}", self.pid))
+    }
+
+    /// The process' command name from `/proc/<pid>/comm`, possibly truncated by the kernel.
+    pub fn comm(&self) -> io::Result<String> {
+        let mut comm = fs::read_to_string(self.path("comm"))?;
+        if comm.ends_with('\n') {
+            comm.pop();
+        }
+        Ok(comm)
+    }
+
+    /// The process' arguments from `/proc/<pid>/cmdline`. Empty for kernel threads and zombies.
+    pub fn cmdline(&self) -> io::Result<Vec<Vec<u8>>> {
+        let buf = fs::read(self.path("cmdline"))?;
+        let mut args: Vec<Vec<u8>> = buf.split(|b| *b == b'\0').map(Vec::from).collect();
+        // cmdline ends with a NUL, so there's an extra empty arg at the end
+        if args.last().is_some_and(Vec::is_empty) {
+            args.pop();
+        }
+        Ok(args)
+    }
+
+    /// A human-readable command line, with the arguments joined by spaces. Like `ps`, this is
+    /// the comm in brackets when there are no arguments.
+    pub fn command_line(&self) -> io::Result<String> {
+        let args = self.cmdline()?;
+        if args.is_empty() {
+            return Ok(format!("[{}]", self.comm()?));
+        }
+        let args: Vec<_> = args.iter().map(|a| String::from_utf8_lossy(a)).collect();
+        Ok(args.join(" "))
+    }
+
+    /// The process' initial environment from `/proc/<pid>/environ`
+    pub fn environ(&self) -> io::Result<EnvBlock> {
+        EnvBlock::from_pid(self.pid)
+    }
+
+    /// The parent's PID from `/proc/<pid>/status`. This is 0 for PID 1 and kernel threads, or if
+    /// the parent is outside of our PID namespace.
+    pub fn ppid(&self) -> io::Result<u32> {
+        let status = fs::read_to_string(self.path("status"))?;
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("PPid:"))
+            .and_then(|ppid| ppid.trim().parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no PPid in status"))
+    }
+
+    /// This process followed by its parent, grandparent, and so on up to PID 1
+    pub fn ancestry(&self) -> io::Result<Vec<Process>> {
+        let mut chain = vec![*self];
+        let mut proc = *self;
+        while proc.pid > 1 {
+            let ppid = proc.ppid()?;
+            // stop at the top of the tree, and don't loop forever if there's a cycle because
+            // PIDs got reused while walking
+            if ppid == 0 || chain.iter().any(|p| p.pid == ppid) {
+                break;
+            }
+            proc = Process::new(ppid);
+            chain.push(proc);
+        }
+        Ok(chain)
+    }
+
+    /// The PIDs of the process' children, in ascending order.
+    ///
+    /// This reads `/proc/<pid>/task/<tid>/children` for each thread, and falls back to scanning
+    /// the parent PIDs of every process when the kernel doesn't provide those files.
+    pub fn children(&self) -> io::Result<Vec<Process>> {
+        let mut pids = Vec::new();
+        for tid in self.threads()? {
+            let children = match fs::read_to_string(self.path(&format!("task/{tid}/children"))) {
+                Ok(children) => children,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    // either the thread exited, or the kernel was built without
+                    // CONFIG_PROC_CHILDREN
+                    if self.path(&format!("task/{tid}")).exists() {
+                        return self.children_by_ppid();
+                    }
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            pids.extend(
+                children
+                    .split_whitespace()
+                    .filter_map(|pid| pid.parse::<u32>().ok()),
+            );
+        }
+        pids.sort_unstable();
+        pids.dedup();
+        Ok(pids.into_iter().map(Process::new).collect())
+    }
+
+    fn children_by_ppid(&self) -> io::Result<Vec<Process>> {
+        Ok(pids()?
+            .into_iter()
+            .map(Process::new)
+            // processes can exit while we're scanning
+            .filter(|proc| proc.ppid().is_ok_and(|ppid| ppid == self.pid))
+            .collect())
+    }
+
+    /// The IDs of the process' threads, in ascending order. The first one is usually the PID.
+    pub fn threads(&self) -> io::Result<Vec<u32>> {
+        let mut tids: Vec<u32> = fs::read_dir(self.path("task"))?
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect();
+        tids.sort_unstable();
+        Ok(tids)
+    }
+
+    /// The environment of one of the process' threads, from `/proc/<pid>/task/<tid>/environ`
+    pub fn thread_environ(&self, tid: u32) -> io::Result<EnvBlock> {
+        EnvBlock::from_file(self.path(&format!("task/{tid}/environ")))
+    }
+
+    /// The process' current environment, including changes made after it started. See
+    /// [`live`](crate::live) for how this works.
+    #[cfg(target_os = "linux")]
+    pub fn live_environ(&self) -> io::Result<EnvBlock> {
+        crate::live::read_live_environ(self.pid)
+    }
+}
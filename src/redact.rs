@@ -0,0 +1,77 @@
+//! Masking of secret values
+
+use std::borrow::Cow;
+
+use clap::ValueEnum;
+use envcat::{Pattern, PatternBuilder, PatternError};
+
+/// Globs for variable names which usually hold secrets, matched case-insensitively.
+pub const SENSITIVE_KEYS: &[&str] = &[
+    "*TOKEN*",
+    "*SECRET*",
+    "*PASSWORD*",
+    "*PASSWD*",
+    "*PASSPHRASE*",
+    "*CREDENTIAL*",
+    "*APIKEY*",
+    "*API_KEY*",
+    "*PRIVATE_KEY*",
+    "*_KEY",
+    "*_AUTH",
+    "*COOKIE*",
+    "*DATABASE_URL*",
+    "AWS_*",
+];
+
+/// How redacted values are displayed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum RedactMode {
+    /// Replace the value with '****'
+    #[default]
+    Mask,
+    /// Replace the value with '****' and a short hash, so that equal values can be recognized
+    Hash,
+}
+
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    keys: Pattern,
+    mode: RedactMode,
+}
+
+impl Redactor {
+    /// Redact [`SENSITIVE_KEYS`] plus the globs in `extra`
+    pub fn new<I, S>(extra: I, mode: RedactMode) -> Result<Self, PatternError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let keys = PatternBuilder::new()
+            .glob(true)
+            .extend(SENSITIVE_KEYS.iter().copied())
+            .extend(extra)
+            .build()?;
+        Ok(Self { keys, mode })
+    }
+
+    /// Get the value to display for `key`. Empty values are left alone since there's nothing
+    /// to hide.
+    pub fn redact<'a>(&self, key: &[u8], value: &'a [u8]) -> Cow<'a, [u8]> {
+        if value.is_empty() || !self.keys.is_match(key) {
+            return Cow::Borrowed(value);
+        }
+        match self.mode {
+            RedactMode::Mask => Cow::Borrowed(b"****"),
+            RedactMode::Hash => Cow::Owned(format!("****{:08x}", short_hash(value)).into_bytes()),
+        }
+    }
+}
+
+/// 32-bit FNV-1a. This needs to be stable across runs and machines so that hashes can be
+/// compared, which rules out std's `DefaultHasher`. It's short enough that it can't be used to
+/// recover anything but trivial secrets.
+fn short_hash(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, b| {
+        (hash ^ u32::from(*b)).wrapping_mul(0x0100_0193)
+    })
+}
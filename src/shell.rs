@@ -0,0 +1,210 @@
+//! Quoting of environment entries as shell assignment statements
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+/// Whether `name` can be used as a variable name in POSIX-ish shells, i.e. it matches
+/// `[A-Za-z_][A-Za-z0-9_]*`
+pub fn is_identifier(name: &[u8]) -> bool {
+    match name.split_first() {
+        Some((first, rest)) => {
+            (first.is_ascii_alphabetic() || *first == b'_')
+                && rest.iter().all(|b| b.is_ascii_alphanumeric() || *b == b'_')
+        }
+        None => false,
+    }
+}
+
+/// `export KEY='value'`, with single quotes escaped as `'\''`.
+///
+/// Everything but `'` is literal inside single quotes, including newlines and non-UTF-8 bytes.
+pub fn write_sh<W: Write>(out: &mut W, key: &[u8], val: &[u8]) -> io::Result<()> {
+    out.write_all(b"export ")?;
+    out.write_all(key)?;
+    out.write_all(b"=")?;
+    write_single_quoted(out, val)?;
+    out.write_all(b"\n")
+}
+
+/// Same as [`write_sh`], except that values with control characters or invalid UTF-8 use
+/// `$'...'` quoting so that the output is printable and survives copy/paste. Works for both bash
+/// and zsh.
+pub fn write_bash<W: Write>(out: &mut W, key: &[u8], val: &[u8]) -> io::Result<()> {
+    if !needs_escapes(val) {
+        return write_sh(out, key, val);
+    }
+
+    out.write_all(b"export ")?;
+    out.write_all(key)?;
+    out.write_all(b"=$'")?;
+    for chunk in val.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => out.write_all(b"\\\\")?,
+                '\'' => out.write_all(b"\\'")?,
+                '\n' => out.write_all(b"\\n")?,
+                '\r' => out.write_all(b"\\r")?,
+                '\t' => out.write_all(b"\\t")?,
+                c if c.is_ascii_control() => write!(out, "\\x{:02x}", c as u32)?,
+                c => write!(out, "{c}")?,
+            }
+        }
+        for b in chunk.invalid() {
+            write!(out, "\\x{b:02x}")?;
+        }
+    }
+    out.write_all(b"'\n")
+}
+
+/// `set -gx KEY 'value'`. Inside fish's single quotes, only `\` and `'` need escaping. Control
+/// characters and invalid UTF-8 are written as `\XHH` byte escapes outside of the quotes.
+pub fn write_fish<W: Write>(out: &mut W, key: &[u8], val: &[u8]) -> io::Result<()> {
+    out.write_all(b"set -gx ")?;
+    out.write_all(key)?;
+    out.write_all(b" '")?;
+    for chunk in val.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => out.write_all(b"\\\\")?,
+                '\'' => out.write_all(b"\\'")?,
+                c if c.is_ascii_control() => write!(out, "'\\X{:02x}'", c as u32)?,
+                c => write!(out, "{c}")?,
+            }
+        }
+        for b in chunk.invalid() {
+            write!(out, "'\\X{b:02x}'")?;
+        }
+    }
+    out.write_all(b"'\n")
+}
+
+/// `$env:KEY = 'value'`, with single quotes (including the typographic ones which PowerShell also
+/// accepts) doubled. Names which aren't plain identifiers use the `${env:...}` form.
+///
+/// PowerShell strings are always Unicode, so invalid UTF-8 can't be represented and gets replaced
+/// with U+FFFD. Returns false if that happened.
+pub fn write_powershell<W: Write>(out: &mut W, key: &[u8], val: &[u8]) -> io::Result<bool> {
+    let key = String::from_utf8_lossy(key);
+    let val_str = String::from_utf8_lossy(val);
+    if is_identifier(key.as_bytes()) {
+        write!(out, "$env:{key} = '")?;
+    } else {
+        out.write_all(b"${env:")?;
+        for c in key.chars() {
+            if matches!(c, '`' | '{' | '}') {
+                out.write_all(b"`")?;
+            }
+            write!(out, "{c}")?;
+        }
+        out.write_all(b"} = '")?;
+    }
+    for c in val_str.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+            write!(out, "{c}")?;
+        }
+        write!(out, "{c}")?;
+    }
+    out.write_all(b"'\n")?;
+    Ok(matches!(key, Cow::Borrowed(_)) && matches!(val_str, Cow::Borrowed(_)))
+}
+
+fn write_single_quoted<W: Write>(out: &mut W, val: &[u8]) -> io::Result<()> {
+    out.write_all(b"'")?;
+    for (i, part) in val.split(|b| *b == b'\'').enumerate() {
+        if i > 0 {
+            out.write_all(b"'\\''")?;
+        }
+        out.write_all(part)?;
+    }
+    out.write_all(b"'")
+}
+
+fn needs_escapes(val: &[u8]) -> bool {
+    std::str::from_utf8(val).map_or(true, |s| s.chars().any(|c| c.is_ascii_control()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type WriteFn = fn(&mut Vec<u8>, &[u8], &[u8]) -> io::Result<()>;
+
+    fn quote(write: WriteFn, key: &[u8], val: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        write(&mut out, key, val).unwrap();
+        out
+    }
+
+    #[test]
+    fn identifier() {
+        assert!(is_identifier(b"_A1"));
+        assert!(!is_identifier(b""));
+        assert!(!is_identifier(b"1A"));
+        assert!(!is_identifier(b"A-B"));
+        assert!(!is_identifier(b"BASH_FUNC_f%%"));
+    }
+
+    #[test]
+    fn sh() {
+        assert_eq!(quote(write_sh, b"A", b""), b"export A=''\n");
+        assert_eq!(
+            quote(write_sh, b"A", b"it's $HOME `x` \\ \"q\"\nline"),
+            b"export A='it'\\''s $HOME `x` \\ \"q\"\nline'\n"
+        );
+        // there's no way to escape bytes in sh, but they're literal inside single quotes
+        assert_eq!(quote(write_sh, b"A", b"\x01\xff"), b"export A='\x01\xff'\n");
+    }
+
+    #[test]
+    fn bash() {
+        // printable values are the same as sh
+        assert_eq!(
+            quote(write_bash, b"A", b"it's $x \xc3\xa9"),
+            b"export A='it'\\''s $x \xc3\xa9'\n"
+        );
+        assert_eq!(
+            quote(write_bash, b"A", b"it's\\\n\r\t\x01\x7f $x \xc3\xa9 \xff"),
+            b"export A=$'it\\'s\\\\\\n\\r\\t\\x01\\x7f $x \xc3\xa9 \\xff'\n"
+        );
+    }
+
+    #[test]
+    fn fish() {
+        assert_eq!(
+            quote(write_fish, b"A", b"it's $x \\ \"q\""),
+            b"set -gx A 'it\\'s $x \\\\ \"q\"'\n"
+        );
+        assert_eq!(
+            quote(write_fish, b"A", b"a\nb\xff\xc3\xa9"),
+            b"set -gx A 'a'\\X0a'b'\\Xff'\xc3\xa9'\n"
+        );
+    }
+
+    #[test]
+    fn powershell() {
+        let quote = |key: &[u8], val: &[u8]| {
+            let mut out = Vec::new();
+            let exact = write_powershell(&mut out, key, val).unwrap();
+            (String::from_utf8(out).unwrap(), exact)
+        };
+        assert_eq!(
+            quote(b"A", "it's \u{2018}x\u{2019} $y `z`\n".as_bytes()),
+            (
+                "$env:A = 'it''s \u{2018}\u{2018}x\u{2019}\u{2019} $y `z`\n'\n".to_owned(),
+                true
+            )
+        );
+        assert_eq!(
+            quote(b"A-B{`}", b"1"),
+            ("${env:A-B`{```}} = '1'\n".to_owned(), true)
+        );
+        assert_eq!(
+            quote(b"A", b"\xff"),
+            ("$env:A = '\u{fffd}'\n".to_owned(), false)
+        );
+        assert_eq!(
+            quote(b"\xff", b"1"),
+            ("${env:\u{fffd}} = '1'\n".to_owned(), false)
+        );
+    }
+}
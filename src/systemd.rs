@@ -0,0 +1,553 @@
+//! Work out the environment that a systemd unit would get from its `Environment=` and
+//! `EnvironmentFile=` settings, without starting it.
+//!
+//! This covers the unit file itself plus its `<unit>.d/*.conf` drop-ins, systemd's quoting and
+//! escaping rules, and the common specifiers like `%n` and `%i`. It doesn't include variables
+//! that systemd sets on its own, like `PATH`, `INVOCATION_ID`, or `HOME` for `User=` units.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::EnvBlock;
+
+/// Directories where system units are installed, highest priority first
+const UNIT_DIRS: &[&str] = &[
+    "/etc/systemd/system",
+    "/run/systemd/system",
+    "/usr/local/lib/systemd/system",
+    "/usr/lib/systemd/system",
+    "/lib/systemd/system",
+];
+
+/// Sections where `Environment=` is allowed
+const EXEC_SECTIONS: &[&str] = &["Service", "Socket", "Mount", "Swap"];
+
+/// Find a unit given either a path or a bare unit name like `foo.service`.
+///
+/// For a template instance like `foo@bar.service`, this is the template `foo@.service` unless
+/// there's a file for the instance itself in any of the unit directories. Pass the instance name
+/// to [`read_unit_as`] so that its specifiers and drop-ins are used.
+pub fn find_unit(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.exists() {
+        return Some(path.to_owned());
+    }
+    let template = UnitName::new(path.file_name()?.to_str()?).template();
+    if name.contains('/') {
+        // if there's no template either, report the path we were given as missing
+        return match template.map(|template| path.with_file_name(template)) {
+            Some(template) if template.exists() => Some(template),
+            _ => Some(path.to_owned()),
+        };
+    }
+    [Some(name), template.as_deref()]
+        .into_iter()
+        .flatten()
+        .flat_map(|name| UNIT_DIRS.iter().map(move |dir| Path::new(dir).join(name)))
+        .find(|path| path.exists())
+}
+
+/// Read the environment configured by the unit file at `path` and its drop-ins
+pub fn read_unit(path: impl AsRef<Path>) -> io::Result<EnvBlock> {
+    let path = path.as_ref();
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid unit path"))?;
+    read_unit_as(path, name)
+}
+
+/// Read the environment configured by the unit file at `path` for the unit `name`, which is
+/// different when `path` is a template and `name` one of its instances.
+pub fn read_unit_as(path: impl AsRef<Path>, name: &str) -> io::Result<EnvBlock> {
+    let path = path.as_ref();
+    let unit = UnitName::new(name);
+
+    let mut settings = Settings::default();
+    settings.parse(&fs::read_to_string(path)?);
+    for drop_in in drop_ins(path, &unit)? {
+        settings.parse(&fs::read_to_string(drop_in)?);
+    }
+
+    let mut env: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    let mut set = |key: &[u8], value: &[u8]| match env.iter_mut().find(|(k, _)| k == key) {
+        Some((_, v)) => *v = value.to_vec(),
+        None => env.push((key.to_vec(), value.to_vec())),
+    };
+
+    for value in &settings.environment {
+        for word in split_words(value) {
+            let word = unit.expand(&word);
+            if let Some((key, value)) = word.split_once('=')
+                && !key.is_empty()
+            {
+                set(key.as_bytes(), value.as_bytes());
+            }
+        }
+    }
+
+    // variables from files override Environment=, regardless of the order they're listed in
+    for file in &settings.environment_files {
+        let file = unit.expand(file);
+        let (optional, file) = match file.strip_prefix('-') {
+            Some(file) => (true, file.to_owned()),
+            None => (false, file),
+        };
+        let buf = match fs::read(&file) {
+            Ok(buf) => buf,
+            Err(err) if optional && err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(io::Error::new(err.kind(), format!("{file}: {err}"))),
+        };
+        for (key, value) in parse_env_file(&buf) {
+            set(&key, &value);
+        }
+    }
+
+    let mut buf = Vec::new();
+    for (key, value) in env {
+        buf.extend_from_slice(&key);
+        buf.push(b'=');
+        buf.extend_from_slice(&value);
+        buf.push(b'\0');
+    }
+    Ok(EnvBlock::new(buf))
+}
+
+/// Find `<name>.d/*.conf` drop-ins in the standard unit directories, plus next to the unit if it
+/// isn't in one of those. Files with the same name in a higher-priority directory hide lower
+/// ones, and the result is sorted by file name, which is the order that systemd applies them in.
+///
+/// Template instances also get the template's drop-ins, `<prefix>@.<type>.d`, with the instance's
+/// own ones taking priority within each directory.
+fn drop_ins(path: &Path, unit: &UnitName) -> io::Result<Vec<PathBuf>> {
+    let mut dir_names = vec![format!("{}.d", unit.full)];
+    if let Some(template) = unit.template() {
+        dir_names.push(format!("{template}.d"));
+    }
+    let mut parents = Vec::new();
+    // a unit outside the standard directories was asked for explicitly, so its own drop-ins come
+    // first
+    if let Some(parent) = path.parent()
+        && !UNIT_DIRS.iter().any(|dir| parent == Path::new(dir))
+    {
+        parents.push(parent);
+    }
+    parents.extend(UNIT_DIRS.iter().map(Path::new));
+    let dirs = parents
+        .into_iter()
+        .flat_map(|parent| dir_names.iter().map(move |name| parent.join(name)));
+
+    let mut found: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for dir in dirs {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let Ok(file_name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if file_name.ends_with(".conf") {
+                found.entry(file_name).or_insert_with(|| entry.path());
+            }
+        }
+    }
+    Ok(found.into_values().collect())
+}
+
+#[derive(Debug, Default)]
+struct Settings {
+    environment: Vec<String>,
+    environment_files: Vec<String>,
+}
+
+impl Settings {
+    /// Collect the settings from one unit file. An empty assignment clears everything set so far.
+    fn parse(&mut self, text: &str) {
+        let mut section = String::new();
+        let mut lines = text.lines();
+        while let Some(line) = lines.next() {
+            let mut line = line.trim().to_owned();
+            // a trailing backslash continues the line, skipping comments in between
+            while line.ends_with('\\') {
+                line.pop();
+                line.push(' ');
+                match lines
+                    .by_ref()
+                    .find(|next| !next.trim_start().starts_with(['#', ';']))
+                {
+                    Some(next) => line.push_str(next.trim()),
+                    None => break,
+                }
+            }
+
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.to_owned();
+                continue;
+            }
+            if !EXEC_SECTIONS.contains(&section.as_str()) {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            let list = match key.trim() {
+                "Environment" => &mut self.environment,
+                "EnvironmentFile" => &mut self.environment_files,
+                _ => continue,
+            };
+            if value.is_empty() {
+                list.clear();
+            } else {
+                list.push(value.to_owned());
+            }
+        }
+    }
+}
+
+/// Split an `Environment=` value into words, handling quotes and C-style escapes
+fn split_words(value: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chars = value.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut word = String::new();
+        let mut quote = None;
+        while let Some(c) = chars.next() {
+            match (c, quote) {
+                (c, None) if c.is_whitespace() => break,
+                ('"' | '\'', None) => quote = Some(c),
+                (c, Some(q)) if c == q => quote = None,
+                ('\\', _) => match chars.next() {
+                    Some('n') => word.push('\n'),
+                    Some('t') => word.push('\t'),
+                    Some('r') => word.push('\r'),
+                    Some('s') => word.push(' '),
+                    Some('x') => {
+                        let hex: String = (0..2).filter_map(|_| chars.next()).collect();
+                        match u8::from_str_radix(&hex, 16) {
+                            Ok(b) if b.is_ascii() => word.push(char::from(b)),
+                            _ => {
+                                word.push_str("\\x");
+                                word.push_str(&hex);
+                            }
+                        }
+                    }
+                    Some(c) => word.push(c),
+                    None => word.push('\\'),
+                },
+                (c, _) => word.push(c),
+            }
+        }
+        words.push(word);
+    }
+    words
+}
+
+/// Parse an `EnvironmentFile=` file the same way as systemd.
+///
+/// Lines starting with `#` or `;` are comments, but there are no comments after a value. Values
+/// can be made of single-quoted, double-quoted, and unquoted parts, and a backslash at the end of
+/// a line continues it. Lines that aren't valid assignments are ignored rather than an error.
+fn parse_env_file(buf: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        PreKey,
+        Key,
+        PreValue,
+        Value,
+        ValueEscape,
+        SingleQuote,
+        DoubleQuote,
+        DoubleQuoteEscape,
+        Comment,
+        CommentEscape,
+    }
+    use State::*;
+
+    let is_space = |c: u8| matches!(c, b' ' | b'\t' | b'\r' | b'\n');
+    let mut entries = Vec::new();
+    let mut push = |key: &mut Vec<u8>, value: &mut Vec<u8>, trailing: Option<usize>| {
+        if let Some(len) = trailing {
+            value.truncate(len);
+        }
+        while key.last().is_some_and(|c| is_space(*c)) {
+            key.pop();
+        }
+        let valid = key.first().is_some_and(|c| !c.is_ascii_digit())
+            && key.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_');
+        if valid {
+            entries.push((std::mem::take(key), std::mem::take(value)));
+        } else {
+            key.clear();
+            value.clear();
+        }
+    };
+
+    let mut state = PreKey;
+    let mut key = Vec::new();
+    let mut value = Vec::new();
+    // where the trailing whitespace of an unquoted value starts
+    let mut trailing = None;
+    for &c in buf {
+        state = match (state, c) {
+            (PreKey, b'#' | b';') => Comment,
+            (PreKey, c) if is_space(c) => PreKey,
+            (PreKey | Key, b'=') => PreValue,
+            (PreKey | Key, b'\n') => {
+                // no '=' on the line
+                key.clear();
+                PreKey
+            }
+            (PreKey | Key, c) => {
+                key.push(c);
+                Key
+            }
+
+            (PreValue | Value, b'\n') => {
+                push(&mut key, &mut value, trailing.take());
+                PreKey
+            }
+            (PreValue, b'\'') => SingleQuote,
+            (PreValue, b'"') => DoubleQuote,
+            (PreValue | Value, b'\\') => {
+                trailing = None;
+                ValueEscape
+            }
+            (PreValue, c) if is_space(c) => PreValue,
+            (PreValue | Value, c) => {
+                if !is_space(c) {
+                    trailing = None;
+                } else if trailing.is_none() {
+                    trailing = Some(value.len());
+                }
+                value.push(c);
+                Value
+            }
+            (ValueEscape, c) => {
+                // an escaped newline continues the line
+                if c != b'\n' {
+                    value.push(c);
+                }
+                Value
+            }
+
+            (SingleQuote, b'\'') | (DoubleQuote, b'"') => PreValue,
+            (SingleQuote, c) => {
+                value.push(c);
+                SingleQuote
+            }
+            (DoubleQuote, b'\\') => DoubleQuoteEscape,
+            (DoubleQuote, c) => {
+                value.push(c);
+                DoubleQuote
+            }
+            (DoubleQuoteEscape, c) => {
+                match c {
+                    b'"' | b'\\' | b'`' | b'$' => value.push(c),
+                    b'\n' => (),
+                    _ => value.extend_from_slice(&[b'\\', c]),
+                }
+                DoubleQuote
+            }
+
+            (Comment, b'\\') => CommentEscape,
+            (Comment, b'\n') => PreKey,
+            (Comment | CommentEscape, _) => Comment,
+        };
+    }
+    // the last line doesn't need a newline, and unterminated quotes are accepted too
+    if !matches!(state, PreKey | Key | Comment | CommentEscape) {
+        push(&mut key, &mut value, trailing);
+    }
+    entries
+}
+
+/// The parts of a unit name used by specifiers
+struct UnitName {
+    /// full name, `foo@bar.service`
+    full: String,
+    /// name without the type suffix, `foo@bar`
+    name: String,
+    /// prefix before the `@`, `foo`
+    prefix: String,
+    /// instance after the `@`, `bar`
+    instance: String,
+}
+
+impl UnitName {
+    fn new(full: &str) -> Self {
+        let name = full.rsplit_once('.').map_or(full, |(name, _)| name);
+        let (prefix, instance) = name.split_once('@').unwrap_or((name, ""));
+        Self {
+            full: full.to_owned(),
+            name: name.to_owned(),
+            prefix: prefix.to_owned(),
+            instance: instance.to_owned(),
+        }
+    }
+
+    /// The template's name for an instance, `foo@.service` for `foo@bar.service`
+    fn template(&self) -> Option<String> {
+        if self.instance.is_empty() {
+            return None;
+        }
+        let suffix = &self.full[self.name.len()..];
+        Some(format!("{}@{suffix}", self.prefix))
+    }
+
+    /// Expand `%` specifiers. Unknown specifiers are left as-is, as are the user specifiers
+    /// `%h`, `%u`, and `%U`, since we don't know which user the unit runs as.
+    fn expand(&self, s: &str) -> String {
+        let mut out = String::with_capacity(s.len());
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            let Some(spec) = chars.next() else {
+                out.push('%');
+                break;
+            };
+            match spec {
+                '%' => out.push('%'),
+                'n' => out.push_str(&self.full),
+                'N' => out.push_str(&self.name),
+                'p' | 'P' => out.push_str(&self.prefix),
+                'i' | 'I' => out.push_str(&self.instance),
+                'f' => {
+                    out.push('/');
+                    out.push_str(if self.instance.is_empty() {
+                        &self.prefix
+                    } else {
+                        &self.instance
+                    });
+                }
+                'H' => out.push_str(&hostname()),
+                'l' => out.push_str(hostname().split('.').next().unwrap_or_default()),
+                't' => out.push_str("/run"),
+                'S' => out.push_str("/var/lib"),
+                'C' => out.push_str("/var/cache"),
+                'L' => out.push_str("/var/log"),
+                'E' => out.push_str("/etc"),
+                'T' => out.push_str("/tmp"),
+                'V' => out.push_str("/var/tmp"),
+                _ => {
+                    out.push('%');
+                    out.push(spec);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|s| s.trim().to_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn continuation_with_comments() {
+        let mut settings = Settings::default();
+        settings.parse("[Service]\nEnvironment=A=1 \\\n# c\n; d\nB=2 \\\nC=3\nEnvironment=D=4\n");
+        let words: Vec<String> = settings
+            .environment
+            .iter()
+            .flat_map(|value| split_words(value))
+            .collect();
+        assert_eq!(words, ["A=1", "B=2", "C=3", "D=4"]);
+    }
+
+    fn env_file(input: &str) -> Vec<String> {
+        parse_env_file(input.as_bytes())
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", str_lossy(&key), str_lossy(&value)))
+            .collect()
+    }
+
+    fn str_lossy(b: &[u8]) -> String {
+        String::from_utf8_lossy(b).into_owned()
+    }
+
+    #[test]
+    fn env_file_comments() {
+        assert_eq!(
+            env_file("# a\n; b\n  ;c\nA=1\nPASSWORD=abc #123\nB=;x\n# d \\\nC=3\n"),
+            ["A=1", "PASSWORD=abc #123", "B=;x"]
+        );
+    }
+
+    #[test]
+    fn env_file_values() {
+        assert_eq!(
+            env_file(
+                "A = spaced  out \t\nB=\nC='single \\ \"'\nD=\"double \\\" \\$ \\a\"\n\
+                 E='two\nlines'\nF=\"one\\\ntwo\"\nG='a' \"b\" c\nH=x\\ \nI=\\ y\r\n"
+            ),
+            [
+                "A=spaced  out",
+                "B=",
+                "C=single \\ \"",
+                "D=double \" $ \\a",
+                "E=two\nlines",
+                "F=onetwo",
+                "G=abc",
+                "H=x ",
+                "I= y",
+            ]
+        );
+    }
+
+    #[test]
+    fn env_file_continuation() {
+        assert_eq!(env_file("A=one \\\ntwo\nB=x\\\n"), ["A=one two", "B=x"]);
+    }
+
+    #[test]
+    fn env_file_invalid_lines() {
+        assert_eq!(
+            env_file("garbage\n=1\n1A=2\nA-B=3\nexport C=4\n\nD=5\nE='unterminated"),
+            ["D=5", "E=unterminated"]
+        );
+    }
+
+    #[test]
+    fn template_name() {
+        let template = |name| UnitName::new(name).template();
+        assert_eq!(template("web@foo.service").as_deref(), Some("web@.service"));
+        assert_eq!(
+            template("web@foo.bar.socket").as_deref(),
+            Some("web@.socket")
+        );
+        assert_eq!(template("web@.service"), None);
+        assert_eq!(template("web.service"), None);
+    }
+
+    #[test]
+    fn specifiers() {
+        let unit = UnitName::new("web@foo.service");
+        assert_eq!(
+            unit.expand("%n %N %p %i %f %% %h %u %U %Z %"),
+            "web@foo.service web@foo web foo /foo % %h %u %U %Z %"
+        );
+    }
+}